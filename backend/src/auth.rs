@@ -6,7 +6,7 @@ use std::{
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
+    http::{header::SET_COOKIE, request::Parts, StatusCode},
     response::{IntoResponse, IntoResponseParts, Response},
     RequestPartsExt,
 };
@@ -17,67 +17,131 @@ use axum_extra::extract::{
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+
+use crate::common::state::{AppState, Db};
 
 pub mod sign_up;
-pub use sign_up::sign_up;
+pub use sign_up::{send_verification_code, sign_up};
 
 pub mod sign_in;
 pub use sign_in::sign_in;
 
+pub mod sign_in_link;
+pub use sign_in_link::{send_sign_in_link, sign_in_with_link};
+
 pub mod current_user;
 pub use current_user::current_user;
 
 pub mod log_out;
-pub use log_out::log_out;
+pub use log_out::{log_out, log_out_everywhere};
+
+pub mod guest;
+pub use guest::create_guest;
+
+pub mod email_token;
+
+pub mod password;
+
+pub mod oidc;
+pub use oidc::{oidc_callback, start_oidc_sign_in};
+
+pub mod reset_password;
+pub use reset_password::{request_password_reset, reset_password};
 
 static KEYS: Lazy<Keys> = Lazy::new(|| {
     let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     Keys::new(jwt_secret.as_bytes())
 });
 
-pub fn password_hash(password: &str, salt: &Vec<u8>) -> Vec<u8> {
-    static PEPPER: Lazy<String> =
-        Lazy::new(|| std::env::var("PEPPER").expect("PEPPER must be set"));
-    let mut hasher = Sha256::new();
-    hasher.update(password);
-    hasher.update(salt);
-    hasher.update(&*PEPPER);
-    hasher.finalize().to_vec()
-}
-
+/// Re-issues the auth token of a signed in user every so often, so that they stay signed in for as
+/// long as they remain active.
 pub async fn refresh_auth_token(
-    mut auth_token: AuthToken,
+    OptionalAuthToken(auth_token): OptionalAuthToken,
     response: Response,
-) -> impl IntoResponse {
+) -> Response {
+    let Some(mut auth_token) = auth_token.filter(AuthToken::is_due_refresh) else {
+        return response;
+    };
+
+    // Responses that set the cookie themselves, such as those of signing in or logging out, take
+    // precedence.
+    let cookie_prefix = format!("{}=", AuthToken::COOKIE_NAME);
+    let sets_cookie = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .any(|cookie| cookie.as_bytes().starts_with(cookie_prefix.as_bytes()));
+    if sets_cookie {
+        return response;
+    }
+
     auth_token.refresh();
-    (auth_token, response)
+    (auth_token, response).into_response()
 }
 
 impl AuthToken {
-    const VALIDITY_DURATION: Duration = Duration::from_secs(60 * 60 * 24); // 1 day
+    /// Sessions expire after this long without any activity.
+    const SESSION_VALIDITY_DURATION: Duration = Duration::from_secs(60 * 60 * 24 * 10); // 10 days
+    /// Sessions and tokens are only kept alive once this long has passed since they last were, so
+    /// that not every request has to write to the database.
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24); // 1 day
     const COOKIE_NAME: &'static str = "signintoken";
 
-    fn new(user_id: u32, username: &str, email: &str) -> Self {
-        let unix_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("now to be after UNIX_EPOCH");
-        let expiry_time = unix_time + Self::VALIDITY_DURATION;
+    async fn new(db: &Db, user_id: u32, username: &str, email: &str) -> Result<Self, sqlx::Error> {
+        Self::with_new_session(db, user_id, username, Some(email.to_owned()), false).await
+    }
 
-        Self {
-            exp: expiry_time.as_secs(),
+    async fn new_guest(db: &Db, user_id: u32, username: &str) -> Result<Self, sqlx::Error> {
+        Self::with_new_session(db, user_id, username, None, true).await
+    }
+
+    async fn with_new_session(
+        db: &Db,
+        user_id: u32,
+        username: &str,
+        email: Option<String>,
+        is_guest: bool,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query("DELETE FROM session WHERE last_active_timestamp < NOW() - INTERVAL ? SECOND")
+            .bind(Self::SESSION_VALIDITY_DURATION.as_secs())
+            .execute(db)
+            .await?;
+
+        let session_id = uuid::Uuid::new_v4().simple().to_string();
+        sqlx::query("INSERT INTO session (id, user_id) VALUES (?, ?)")
+            .bind(&session_id)
+            .bind(user_id)
+            .execute(db)
+            .await?;
+
+        Ok(Self {
+            exp: Self::expiry_time(),
+            session_id,
             user_id,
             username: username.to_owned(),
-            email: email.to_owned(),
-        }
+            email,
+            is_guest,
+        })
+    }
+
+    /// The user's ID, if they are a guest.
+    fn guest_id(&self) -> Option<u32> {
+        self.is_guest.then_some(self.user_id)
     }
 
     fn refresh(&mut self) {
+        self.exp = Self::expiry_time();
+    }
+
+    fn is_due_refresh(&self) -> bool {
+        self.exp + Self::REFRESH_INTERVAL.as_secs() < Self::expiry_time()
+    }
+
+    fn expiry_time() -> u64 {
         let unix_time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("now to be after UNIX_EPOCH");
-        let expiry_time = unix_time + Self::VALIDITY_DURATION;
-        self.exp = expiry_time.as_secs();
+        (unix_time + Self::SESSION_VALIDITY_DURATION).as_secs()
     }
 
     fn into_cookie(self) -> Cookie<'static> {
@@ -87,18 +151,26 @@ impl AuthToken {
         cookie.set_same_site(SameSite::Strict);
         cookie.set_secure(true);
         cookie.set_http_only(true);
+        cookie.set_max_age(time::Duration::seconds(
+            Self::SESSION_VALIDITY_DURATION.as_secs() as i64,
+        ));
         cookie
     }
 }
 
 #[async_trait]
-impl<S> FromRequestParts<S> for AuthToken
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<AppState> for AuthToken {
     type Rejection = AuthTokenRejection;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // The token may have already been checked earlier on in handling the request.
+        if let Some(auth_token) = parts.extensions.get::<AuthToken>() {
+            return Ok(auth_token.clone());
+        }
+
         let jar = parts
             .extract::<CookieJar>()
             .await
@@ -111,10 +183,49 @@ where
         let auth_token =
             jsonwebtoken::decode::<AuthToken>(jwt, &KEYS.decoding, &Validation::default())?.claims;
 
+        // The session must not have been revoked or have expired.
+        let is_session_due_refresh: bool = sqlx::query_scalar(
+            "SELECT last_active_timestamp < NOW() - INTERVAL ? SECOND FROM session WHERE id = ? AND user_id = ? AND last_active_timestamp >= NOW() - INTERVAL ? SECOND",
+        )
+        .bind(Self::REFRESH_INTERVAL.as_secs())
+        .bind(&auth_token.session_id)
+        .bind(auth_token.user_id)
+        .bind(Self::SESSION_VALIDITY_DURATION.as_secs())
+        .fetch_optional(&state.db())
+        .await
+        .map_err(|_| AuthTokenRejection::Other)?
+        .ok_or(AuthTokenRejection::Revoked)?;
+
+        // Sessions are kept alive by being used.
+        if is_session_due_refresh {
+            sqlx::query("UPDATE session SET last_active_timestamp = NOW() WHERE id = ?")
+                .bind(&auth_token.session_id)
+                .execute(&state.db())
+                .await
+                .map_err(|_| AuthTokenRejection::Other)?;
+        }
+
+        parts.extensions.insert(auth_token.clone());
         Ok(auth_token)
     }
 }
 
+#[async_trait]
+impl FromRequestParts<AppState> for OptionalAuthToken {
+    type Rejection = AuthTokenRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        match AuthToken::from_request_parts(parts, state).await {
+            Ok(auth_token) => Ok(Self(Some(auth_token))),
+            Err(AuthTokenRejection::Other) => Err(AuthTokenRejection::Other),
+            Err(_) => Ok(Self(None)),
+        }
+    }
+}
+
 impl IntoResponseParts for AuthToken {
     type Error = Infallible;
 
@@ -140,7 +251,7 @@ impl From<jsonwebtoken::errors::Error> for AuthTokenRejection {
 
         match error.kind() {
             ErrorKind::ExpiredSignature => Self::Expired,
-            _ => Self::Other,
+            _ => Self::Invalid,
         }
     }
 }
@@ -149,25 +260,37 @@ impl IntoResponse for AuthTokenRejection {
     fn into_response(self) -> axum::response::Response {
         let (status_code, body) = match self {
             Self::CookieNotFound => (StatusCode::UNPROCESSABLE_ENTITY, "Sign in JWT not found"),
+            Self::Invalid => (StatusCode::UNPROCESSABLE_ENTITY, "Sign in JWT invalid"),
             Self::Expired => (StatusCode::UNPROCESSABLE_ENTITY, "Sign in JWT expired"),
+            Self::Revoked => (StatusCode::UNPROCESSABLE_ENTITY, "Sign in JWT revoked"),
             Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
         };
         (status_code, body).into_response()
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthToken {
     exp: u64,
+    session_id: String,
     pub user_id: u32,
     pub username: String,
-    pub email: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub is_guest: bool,
 }
 
+/// The auth token of the user if they are signed in. Unlike with `Option<AuthToken>`, failing to
+/// check the token fails the request instead of treating the user as not signed in.
+#[derive(Debug, Clone)]
+pub struct OptionalAuthToken(pub Option<AuthToken>);
+
 #[derive(Debug, Serialize)]
 pub enum AuthTokenRejection {
     CookieNotFound,
+    Invalid,
     Expired,
+    Revoked,
     Other,
 }
 
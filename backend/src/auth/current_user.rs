@@ -19,6 +19,7 @@ pub async fn current_user(
     Ok(Json(CurrentUserResponse {
         username: auth_token.username,
         email: auth_token.email,
+        is_guest: auth_token.is_guest,
         preferences,
     }))
 }
@@ -27,6 +28,7 @@ pub async fn current_user(
 #[serde(rename_all = "camelCase")]
 pub struct CurrentUserResponse {
     username: String,
-    email: String,
+    email: Option<String>,
+    is_guest: bool,
     preferences: Preferences,
 }
@@ -0,0 +1,169 @@
+//! Storage of the single-use tokens that are sent to users' emails, such as verification codes and
+//! password reset and sign in links.
+
+use std::fmt::Display;
+
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::MySqlConnection;
+
+use crate::common::state::Db;
+
+/// At most this many tokens may be issued for the same email and purpose per hour.
+const MAX_TOKENS_PER_HOUR: i64 = 5;
+/// Tokens for the same email and purpose may be issued at most once in this duration.
+const MIN_TIME_BETWEEN_TOKENS: Duration = Duration::seconds(60);
+/// Tokens are invalidated after this many failed attempts at using them.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTokenPurpose {
+    Verification,
+    PasswordReset,
+    SignIn,
+}
+
+impl EmailTokenPurpose {
+    fn validity_duration(&self) -> Duration {
+        match self {
+            Self::Verification => Duration::minutes(10),
+            Self::PasswordReset => Duration::hours(1),
+            Self::SignIn => Duration::minutes(15),
+        }
+    }
+}
+
+impl Display for EmailTokenPurpose {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Verification => write!(f, "verification"),
+            Self::PasswordReset => write!(f, "password_reset"),
+            Self::SignIn => write!(f, "sign_in"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EmailTokenError {
+    TooManyRequests,
+    Other,
+}
+
+impl From<sqlx::Error> for EmailTokenError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+/// Stores a token to be sent to the given email, provided that not too many have been issued for
+/// it recently.
+pub async fn issue_email_token(
+    db: &Db,
+    purpose: EmailTokenPurpose,
+    email: &str,
+    token: &str,
+) -> Result<(), EmailTokenError> {
+    sqlx::query("DELETE FROM email_token WHERE created_timestamp < NOW() - INTERVAL 1 DAY")
+        .execute(db)
+        .await?;
+
+    // The limits are checked by the same statement that stores the token, so that concurrent
+    // requests can't both get past them.
+    let issued = sqlx::query(
+        "INSERT INTO email_token (purpose, email, token_hash, expiry_timestamp)
+        SELECT ?, ?, ?, ? FROM (
+            SELECT COUNT(*) AS n_tokens, MAX(created_timestamp) AS last_created_timestamp FROM email_token
+            WHERE purpose = ? AND email = ? AND created_timestamp > NOW() - INTERVAL 1 HOUR
+        ) AS recent
+        WHERE recent.n_tokens < ? AND (recent.last_created_timestamp IS NULL OR recent.last_created_timestamp <= NOW() - INTERVAL ? SECOND)",
+    )
+    .bind(purpose.to_string())
+    .bind(email)
+    .bind(token_hash(token))
+    .bind(Utc::now() + purpose.validity_duration())
+    .bind(purpose.to_string())
+    .bind(email)
+    .bind(MAX_TOKENS_PER_HOUR)
+    .bind(MIN_TIME_BETWEEN_TOKENS.num_seconds())
+    .execute(db)
+    .await?
+    .rows_affected()
+        > 0;
+    if !issued {
+        return Err(EmailTokenError::TooManyRequests);
+    }
+
+    Ok(())
+}
+
+/// Uses up a token that was issued to the given email, returning whether it was valid. Every
+/// failed attempt counts towards invalidating the outstanding tokens for the email.
+pub async fn consume_email_token(
+    conn: &mut MySqlConnection,
+    purpose: EmailTokenPurpose,
+    email: &str,
+    token: &str,
+) -> Result<bool, sqlx::Error> {
+    let consumed = sqlx::query(
+        "UPDATE email_token SET consumed = TRUE WHERE purpose = ? AND email = ? AND token_hash = ? AND NOT consumed AND failed_attempts < ? AND expiry_timestamp > NOW()",
+    )
+    .bind(purpose.to_string())
+    .bind(email)
+    .bind(token_hash(token))
+    .bind(MAX_FAILED_ATTEMPTS)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if !consumed {
+        sqlx::query(
+            "UPDATE email_token SET failed_attempts = failed_attempts + 1 WHERE purpose = ? AND email = ? AND NOT consumed",
+        )
+        .bind(purpose.to_string())
+        .bind(email)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(consumed)
+}
+
+/// Uses up a token that was sent as part of a link, returning the email it was issued to if it was
+/// valid. Link tokens are long enough that they can't be guessed, so failed attempts aren't counted.
+pub async fn consume_email_link(
+    conn: &mut MySqlConnection,
+    purpose: EmailTokenPurpose,
+    token: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let token_hash = token_hash(token);
+    let consumed = sqlx::query(
+        "UPDATE email_token SET consumed = TRUE WHERE purpose = ? AND token_hash = ? AND NOT consumed AND expiry_timestamp > NOW()",
+    )
+    .bind(purpose.to_string())
+    .bind(&token_hash)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if !consumed {
+        return Ok(None);
+    }
+
+    sqlx::query_scalar("SELECT email FROM email_token WHERE purpose = ? AND token_hash = ?")
+        .bind(purpose.to_string())
+        .bind(&token_hash)
+        .fetch_optional(&mut *conn)
+        .await
+}
+
+/// Generates a token to be sent as part of a link.
+pub fn generate_link_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn token_hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token).to_vec()
+}
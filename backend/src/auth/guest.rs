@@ -0,0 +1,51 @@
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::common::{error::AppError, state::Db};
+use crate::preferences::Preferences;
+
+use super::AuthToken;
+
+/// Usernames of guests start with this prefix, which is reserved for them.
+pub const GUEST_USERNAME_PREFIX: &str = "guest_";
+
+pub async fn create_guest(
+    db: Db,
+    Json(CreateGuestParams { preferences }): Json<CreateGuestParams>,
+) -> Result<(AuthToken, Json<CreateGuestResponse>), AppError> {
+    let preferences = preferences.to_string();
+
+    // Guest usernames are random, so a collision is unlikely but not impossible.
+    let mut attempts = 0;
+    let (user_id, username) = loop {
+        let username = format!("{GUEST_USERNAME_PREFIX}{:08x}", rand::random::<u32>());
+        let result =
+            sqlx::query("INSERT INTO user (username, is_guest, preferences) VALUES (?, TRUE, ?)")
+                .bind(&username)
+                .bind(&preferences)
+                .execute(&db)
+                .await;
+
+        match result {
+            Ok(result) => break (result.last_insert_id() as u32, username),
+            Err(sqlx::Error::Database(error)) if error.is_unique_violation() && attempts < 3 => {
+                attempts += 1;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    };
+
+    let auth_token = AuthToken::new_guest(&db, user_id, &username).await?;
+    Ok((auth_token, Json(CreateGuestResponse { username })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGuestParams {
+    preferences: Preferences,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateGuestResponse {
+    username: String,
+}
@@ -5,12 +5,42 @@ use axum_extra::extract::{
 };
 use serde::Serialize;
 
-use super::AuthToken;
+use super::{AuthToken, OptionalAuthToken};
+use crate::common::{error::AppError, state::Db};
 
-pub async fn log_out(jar: CookieJar) -> (CookieJar, Json<LogOutResponse>) {
+pub async fn log_out(
+    db: Db,
+    OptionalAuthToken(auth_token): OptionalAuthToken,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<LogOutResponse>), AppError> {
+    if let Some(auth_token) = auth_token {
+        sqlx::query("DELETE FROM session WHERE id = ?")
+            .bind(auth_token.session_id)
+            .execute(&db)
+            .await?;
+    }
+
+    Ok((remove_cookie(jar), Json(LogOutResponse)))
+}
+
+/// Logs out of every session of the user, including those on other devices.
+pub async fn log_out_everywhere(
+    db: Db,
+    auth_token: AuthToken,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<LogOutResponse>), AppError> {
+    sqlx::query("DELETE FROM session WHERE user_id = ?")
+        .bind(auth_token.user_id)
+        .execute(&db)
+        .await?;
+
+    Ok((remove_cookie(jar), Json(LogOutResponse)))
+}
+
+fn remove_cookie(jar: CookieJar) -> CookieJar {
     let mut cookie = Cookie::from(AuthToken::COOKIE_NAME);
     cookie.set_same_site(SameSite::Strict);
-    (jar.remove(cookie), Json(LogOutResponse))
+    jar.remove(cookie)
 }
 
 #[derive(Debug, Serialize)]
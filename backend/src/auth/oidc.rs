@@ -0,0 +1,344 @@
+//! Signing up and signing in through external OpenID Connect providers (say, Google), using the
+//! authorization code flow with PKCE.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Header, Validation};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::MySqlConnection;
+
+use super::email_token::generate_link_token;
+use super::guest::GUEST_USERNAME_PREFIX;
+use super::sign_in::{complete_sign_in, SignInError};
+use super::sign_up::validation::validate_username;
+use super::{AuthToken, OptionalAuthToken, KEYS};
+use crate::common::email::FRONTEND_URL;
+use crate::common::state::Db;
+use crate::preferences::Preferences;
+
+pub mod provider;
+use provider::{provider, IdTokenClaims};
+
+pub async fn start_oidc_sign_in(
+    OptionalAuthToken(guest): OptionalAuthToken,
+    Path(provider_name): Path<String>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), OidcError> {
+    let provider = provider(&provider_name).ok_or(OidcError::UnknownProvider)?;
+
+    let flow = OidcFlow::new(provider_name, guest.and_then(|guest| guest.guest_id()));
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&flow.code_verifier));
+    let authorization_url = provider
+        .authorization_url(&flow.state, &flow.nonce, &code_challenge)
+        .await?;
+
+    Ok((
+        jar.add(flow.into_cookie()),
+        Redirect::to(&authorization_url),
+    ))
+}
+
+pub async fn oidc_callback(
+    db: Db,
+    Path(provider_name): Path<String>,
+    Query(params): Query<OidcCallbackParams>,
+    jar: CookieJar,
+) -> Result<(CookieJar, AuthToken, Redirect), OidcError> {
+    let provider = provider(&provider_name).ok_or(OidcError::UnknownProvider)?;
+
+    // The flow must have been started by this browser, for this provider.
+    let flow = jar
+        .get(OidcFlow::COOKIE_NAME)
+        .and_then(|cookie| {
+            jsonwebtoken::decode::<OidcFlow>(cookie.value(), &KEYS.decoding, &Validation::default())
+                .ok()
+        })
+        .map(|flow| flow.claims)
+        .filter(|flow| flow.provider == provider_name)
+        .ok_or(OidcError::InvalidFlow)?;
+    if params.error.is_some() {
+        return Err(OidcError::Denied);
+    }
+    if params.state.as_deref() != Some(&flow.state) {
+        return Err(OidcError::InvalidFlow);
+    }
+    let code = params.code.ok_or(OidcError::InvalidFlow)?;
+    // The sign in cookie isn't sent along with the redirect from the provider, so the guest, if
+    // any, is the one who started the flow, provided they haven't since become an actual account.
+    let guest_id = match flow.guest_id {
+        Some(guest_id) => {
+            sqlx::query_scalar("SELECT id FROM user WHERE id = ? AND is_guest")
+                .bind(guest_id)
+                .fetch_optional(&db)
+                .await?
+        }
+        None => None,
+    };
+
+    let id_token = provider.exchange_code(&code, &flow.code_verifier).await?;
+    let claims = provider.verify_id_token(&id_token, &flow.nonce).await?;
+
+    let user_id =
+        match sqlx::query_scalar("SELECT user_id FROM identity WHERE provider = ? AND subject = ?")
+            .bind(&provider_name)
+            .bind(&claims.sub)
+            .fetch_optional(&db)
+            .await?
+        {
+            Some(user_id) => user_id,
+            None => link_identity(&db, guest_id, &provider_name, claims).await?,
+        };
+
+    let row = sqlx::query("SELECT * FROM user WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(&db)
+        .await?;
+    let (auth_token, _) = complete_sign_in(&db, guest_id, row).await?;
+
+    let mut cookie = Cookie::from(OidcFlow::COOKIE_NAME);
+    cookie.set_path(OidcFlow::COOKIE_PATH);
+    Ok((
+        jar.remove(cookie),
+        auth_token,
+        Redirect::to(FRONTEND_URL.as_str()),
+    ))
+}
+
+/// Links an identity that hasn't been seen before to the account with its email, creating the
+/// account if there is none. Only emails verified by the provider are trusted for this.
+async fn link_identity(
+    db: &Db,
+    guest_id: Option<u32>,
+    provider_name: &str,
+    claims: IdTokenClaims,
+) -> Result<u32, OidcError> {
+    let email = claims
+        .email
+        .filter(|_| claims.email_verified)
+        .ok_or(OidcError::EmailNotVerified)?;
+
+    let mut tx = db.begin().await?;
+    let user_id = match sqlx::query_scalar("SELECT id FROM user WHERE email = ? AND NOT is_guest")
+        .bind(&email)
+        .fetch_optional(&mut *tx)
+        .await?
+    {
+        Some(user_id) => user_id,
+        None => create_account(&mut tx, guest_id, &email, claims.name.as_deref()).await?,
+    };
+
+    sqlx::query("INSERT INTO identity (provider, subject, user_id) VALUES (?, ?, ?)")
+        .bind(provider_name)
+        .bind(&claims.sub)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(user_id)
+}
+
+/// Creates an account without a password, with a username derived from the user's name or email.
+async fn create_account(
+    conn: &mut MySqlConnection,
+    guest_id: Option<u32>,
+    email: &str,
+    name: Option<&str>,
+) -> Result<u32, OidcError> {
+    let mut base_username: String = name
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default())
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+        .take(20)
+        .collect();
+    if base_username.len() < 6 || base_username.starts_with(GUEST_USERNAME_PREFIX) {
+        base_username = format!("user_{base_username}");
+    }
+
+    // The username may well be taken, in which case random digits are appended to it.
+    for attempt in 0..5 {
+        let username = if attempt == 0 {
+            base_username.to_owned()
+        } else {
+            format!(
+                "{base_username}{:04}",
+                rand::thread_rng().gen_range(0..10_000)
+            )
+        };
+        if validate_username(&username).is_err() {
+            continue;
+        }
+
+        match insert_account(conn, guest_id, &username, email).await {
+            Ok(user_id) => return Ok(user_id),
+            Err(sqlx::Error::Database(error)) if error.is_unique_violation() => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Err(OidcError::Other)
+}
+
+/// As with signing up, a guest has their guest account turned into the actual account.
+async fn insert_account(
+    conn: &mut MySqlConnection,
+    guest_id: Option<u32>,
+    username: &str,
+    email: &str,
+) -> Result<u32, sqlx::Error> {
+    if let Some(guest_id) = guest_id {
+        let upgraded_guest = sqlx::query(
+            "UPDATE user SET username = ?, email = ?, is_guest = FALSE WHERE id = ? AND is_guest",
+        )
+        .bind(username)
+        .bind(email)
+        .bind(guest_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if upgraded_guest {
+            // The guest's sessions are replaced by one for the actual account.
+            sqlx::query("DELETE FROM session WHERE user_id = ?")
+                .bind(guest_id)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(guest_id);
+        }
+    }
+
+    let user_id = sqlx::query("INSERT INTO user (username, email, preferences) VALUES (?, ?, ?)")
+        .bind(username)
+        .bind(email)
+        .bind(Preferences::default().to_string())
+        .execute(&mut *conn)
+        .await?
+        .last_insert_id() as u32;
+    Ok(user_id)
+}
+
+impl OidcFlow {
+    const COOKIE_NAME: &'static str = "oidcflow";
+    const COOKIE_PATH: &'static str = "/oidc";
+    const VALIDITY_DURATION: Duration = Duration::from_secs(60 * 10); // 10 minutes
+
+    fn new(provider: String, guest_id: Option<u32>) -> Self {
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("now to be after UNIX_EPOCH");
+        Self {
+            exp: (unix_time + Self::VALIDITY_DURATION).as_secs(),
+            provider,
+            state: generate_link_token(),
+            nonce: generate_link_token(),
+            code_verifier: generate_link_token(),
+            guest_id,
+        }
+    }
+
+    fn into_cookie(self) -> Cookie<'static> {
+        let jwt = jsonwebtoken::encode(&Header::default(), &self, &KEYS.encoding)
+            .expect("Default headers and algorithm used");
+        let mut cookie = Cookie::new(Self::COOKIE_NAME, jwt);
+        // The provider redirects back to us, which wouldn't carry a strictly same-site cookie.
+        cookie.set_same_site(SameSite::Lax);
+        cookie.set_secure(true);
+        cookie.set_http_only(true);
+        cookie.set_path(Self::COOKIE_PATH);
+        cookie
+    }
+}
+
+impl From<sqlx::Error> for OidcError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl From<reqwest::Error> for OidcError {
+    fn from(_error: reqwest::Error) -> Self {
+        Self::ProviderError
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for OidcError {
+    fn from(_error: jsonwebtoken::errors::Error) -> Self {
+        Self::InvalidIdToken
+    }
+}
+
+impl From<SignInError> for OidcError {
+    fn from(_error: SignInError) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for OidcError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::UnknownProvider => (StatusCode::NOT_FOUND, "Unknown sign in provider"),
+            Self::InvalidFlow => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Sign in is invalid or has expired, try again",
+            ),
+            Self::Denied => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Sign in was denied by the provider",
+            ),
+            Self::ProviderError => (
+                StatusCode::BAD_GATEWAY,
+                "Sign in provider could not be reached",
+            ),
+            Self::InvalidIdToken => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Sign in provider returned an invalid ID token",
+            ),
+            Self::EmailNotVerified => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Email must be verified with the sign in provider",
+            ),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+/// The state of an ongoing flow, kept in a cookie until the provider redirects back to us.
+#[derive(Debug, Serialize, Deserialize)]
+struct OidcFlow {
+    exp: u64,
+    provider: String,
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    guest_id: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub enum OidcError {
+    UnknownProvider,
+    InvalidFlow,
+    Denied,
+    ProviderError,
+    InvalidIdToken,
+    EmailNotVerified,
+    Other,
+}
@@ -0,0 +1,207 @@
+//! Hashing of passwords with Argon2id. Hashes are stored as PHC strings, which carry the salt and
+//! the cost they were computed with. Accounts created before then have SHA-256 hashes with a
+//! separately stored salt, which are upgraded when their users next sign in.
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+static PEPPER: Lazy<String> = Lazy::new(|| std::env::var("PEPPER").expect("PEPPER must be set"));
+
+/// The cost of newly computed hashes, which can be tuned through the environment.
+static PARAMS: Lazy<Params> = Lazy::new(|| {
+    let cost = |name: &str, default: u32| match std::env::var(name) {
+        Ok(cost) => cost
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} must be a number")),
+        Err(_) => default,
+    };
+    Params::new(
+        cost("ARGON2_MEMORY_COST", Params::DEFAULT_M_COST),
+        cost("ARGON2_TIME_COST", Params::DEFAULT_T_COST),
+        cost("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        None,
+    )
+    .expect("Argon2 cost must be valid")
+});
+
+static ARGON2: Lazy<Argon2<'static>> = Lazy::new(|| {
+    Argon2::new_with_secret(
+        PEPPER.as_bytes(),
+        Algorithm::Argon2id,
+        Version::V0x13,
+        PARAMS.clone(),
+    )
+    .expect("PEPPER must be a valid Argon2 secret")
+});
+
+/// A password hash as stored in the `user` table.
+pub struct StoredPasswordHash {
+    /// Only present for legacy SHA-256 hashes.
+    pub salt: Option<Vec<u8>>,
+    pub password_hash: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordVerification {
+    Incorrect,
+    Correct,
+    /// The password is correct, but its hash is outdated and should be replaced.
+    CorrectNeedsRehash,
+}
+
+/// Hashes a password into a PHC string. Hashing is deliberately expensive, so it is done off of the
+/// async runtime.
+pub async fn hash_password(password: String) -> String {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        ARGON2
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Hashing with valid params to succeed")
+            .to_string()
+    })
+    .await
+    .expect("Hashing not to panic")
+}
+
+pub async fn verify_password(password: String, stored: StoredPasswordHash) -> PasswordVerification {
+    tokio::task::spawn_blocking(move || match stored.salt {
+        Some(salt) => verify_legacy_password(&password, &salt, &stored.password_hash),
+        None => verify_argon2_password(&password, &stored.password_hash),
+    })
+    .await
+    .expect("Verification not to panic")
+}
+
+fn verify_argon2_password(password: &str, password_hash: &[u8]) -> PasswordVerification {
+    let Some(password_hash) = std::str::from_utf8(password_hash)
+        .ok()
+        .and_then(|password_hash| PasswordHash::new(password_hash).ok())
+    else {
+        return PasswordVerification::Incorrect;
+    };
+
+    if ARGON2
+        .verify_password(password.as_bytes(), &password_hash)
+        .is_err()
+    {
+        return PasswordVerification::Incorrect;
+    }
+
+    let is_outdated = password_hash.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&password_hash).map_or(true, |params| {
+            params.m_cost() != PARAMS.m_cost()
+                || params.t_cost() != PARAMS.t_cost()
+                || params.p_cost() != PARAMS.p_cost()
+        });
+    if is_outdated {
+        PasswordVerification::CorrectNeedsRehash
+    } else {
+        PasswordVerification::Correct
+    }
+}
+
+fn verify_legacy_password(
+    password: &str,
+    salt: &[u8],
+    password_hash: &[u8],
+) -> PasswordVerification {
+    let mut hasher = Sha256::new();
+    hasher.update(password);
+    hasher.update(salt);
+    hasher.update(&*PEPPER);
+    if hasher.finalize().as_slice() == password_hash {
+        PasswordVerification::CorrectNeedsRehash
+    } else {
+        PasswordVerification::Incorrect
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_pepper() {
+        std::env::set_var("PEPPER", "pepper");
+    }
+
+    fn stored(password_hash: String) -> StoredPasswordHash {
+        StoredPasswordHash {
+            salt: None,
+            password_hash: password_hash.into_bytes(),
+        }
+    }
+
+    #[tokio::test]
+    async fn verifies_argon2_hashes() {
+        set_pepper();
+        let password_hash = hash_password("hunter22".to_owned()).await;
+        assert!(password_hash.starts_with("$argon2id$"));
+        assert_eq!(
+            verify_password("hunter22".to_owned(), stored(password_hash.clone())).await,
+            PasswordVerification::Correct,
+        );
+        assert_eq!(
+            verify_password("hunter23".to_owned(), stored(password_hash)).await,
+            PasswordVerification::Incorrect,
+        );
+        assert_eq!(
+            verify_password("hunter22".to_owned(), stored("garbage".to_owned())).await,
+            PasswordVerification::Incorrect,
+        );
+    }
+
+    #[tokio::test]
+    async fn asks_for_legacy_hashes_to_be_upgraded() {
+        set_pepper();
+        let salt = b"0123456789abcdef".to_vec();
+        let password_hash = Sha256::new()
+            .chain_update("hunter22")
+            .chain_update(&salt)
+            .chain_update("pepper")
+            .finalize()
+            .to_vec();
+        let stored = |password_hash: &[u8]| StoredPasswordHash {
+            salt: Some(salt.clone()),
+            password_hash: password_hash.to_vec(),
+        };
+        assert_eq!(
+            verify_password("hunter22".to_owned(), stored(&password_hash)).await,
+            PasswordVerification::CorrectNeedsRehash,
+        );
+        assert_eq!(
+            verify_password("hunter23".to_owned(), stored(&password_hash)).await,
+            PasswordVerification::Incorrect,
+        );
+    }
+
+    #[tokio::test]
+    async fn asks_for_hashes_with_other_costs_to_be_upgraded() {
+        set_pepper();
+        let params = Params::new(
+            PARAMS.m_cost() / 2,
+            PARAMS.t_cost() + 1,
+            PARAMS.p_cost(),
+            None,
+        )
+        .unwrap();
+        let argon2 =
+            Argon2::new_with_secret(b"pepper", Algorithm::Argon2id, Version::V0x13, params)
+                .unwrap();
+        let password_hash = argon2
+            .hash_password(b"hunter22", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            verify_password("hunter22".to_owned(), stored(password_hash.clone())).await,
+            PasswordVerification::CorrectNeedsRehash,
+        );
+        assert_eq!(
+            verify_password("hunter23".to_owned(), stored(password_hash)).await,
+            PasswordVerification::Incorrect,
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use axum::http::StatusCode;
+use axum::{response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use super::email_token::{
+    consume_email_link, generate_link_token, issue_email_token, EmailTokenError, EmailTokenPurpose,
+};
+use super::password::hash_password;
+use super::sign_in::UsernameOrEmail;
+use super::sign_up::{validation::validate_password, SignUpError};
+use crate::common::email::{send_email, FRONTEND_URL};
+use crate::common::state::{Db, Mailer};
+
+pub async fn request_password_reset(
+    db: Db,
+    mailer: Mailer,
+    Json(RequestPasswordResetParams { username_or_email }): Json<RequestPasswordResetParams>,
+) -> Result<Json<()>, RequestPasswordResetError> {
+    let email: Option<String> = match username_or_email {
+        UsernameOrEmail::Email(email) => {
+            sqlx::query_scalar("SELECT email FROM user WHERE email = ? AND NOT is_guest")
+                .bind(email)
+                .fetch_optional(&db)
+                .await?
+        }
+        UsernameOrEmail::Username(username) => {
+            sqlx::query_scalar("SELECT email FROM user WHERE username = ? AND NOT is_guest")
+                .bind(username)
+                .fetch_optional(&db)
+                .await?
+        }
+    };
+
+    // Whether an account exists isn't revealed to whoever asked for the reset.
+    let Some(email) = email else {
+        return Ok(Json(()));
+    };
+
+    // The email is sent in the background, so that how long the request takes doesn't reveal it
+    // either.
+    tokio::spawn(async move {
+        let token = generate_link_token();
+        // Only existing accounts are rate limited, so requests over the limit are dropped without
+        // saying so as well.
+        match issue_email_token(&db, EmailTokenPurpose::PasswordReset, &email, &token).await {
+            Ok(()) => {}
+            Err(EmailTokenError::TooManyRequests) => return,
+            Err(EmailTokenError::Other) => {
+                tracing::error!("Failed to issue password reset token");
+                return;
+            }
+        }
+
+        if let Err(error) = send_email(
+            &mailer,
+            &email,
+            "Reset your password",
+            format!(
+                "Use the following link to reset your password: {}/reset-password?token={token}\nIt expires in 1 hour. If you didn't ask to reset your password, you can ignore this email.",
+                *FRONTEND_URL
+            ),
+        )
+        .await
+        {
+            tracing::error!("Failed to send password reset email: {error}");
+        }
+    });
+
+    Ok(Json(()))
+}
+
+pub async fn reset_password(
+    db: Db,
+    Json(ResetPasswordParams { token, password }): Json<ResetPasswordParams>,
+) -> Result<Json<()>, ResetPasswordError> {
+    validate_password(&password)?;
+
+    let mut tx = db.begin().await?;
+    let email = consume_email_link(&mut tx, EmailTokenPurpose::PasswordReset, &token)
+        .await?
+        .ok_or(ResetPasswordError::InvalidLink)?;
+
+    let password_hash = hash_password(password).await;
+
+    sqlx::query("UPDATE user SET salt = NULL, password_hash = ? WHERE email = ? AND NOT is_guest")
+        .bind(password_hash)
+        .bind(&email)
+        .execute(&mut *tx)
+        .await?;
+
+    // Every session of the user is revoked, in case the reset was prompted by a compromise.
+    sqlx::query(
+        "DELETE session FROM session JOIN user ON user.id = session.user_id WHERE user.email = ?",
+    )
+    .bind(email)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(()))
+}
+
+impl From<sqlx::Error> for RequestPasswordResetError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for RequestPasswordResetError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+impl From<sqlx::Error> for ResetPasswordError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl From<SignUpError> for ResetPasswordError {
+    fn from(error: SignUpError) -> Self {
+        match error {
+            SignUpError::InvalidPassword(reason) => Self::InvalidPassword(reason),
+            _ => Self::Other,
+        }
+    }
+}
+
+impl IntoResponse for ResetPasswordError {
+    fn into_response(self) -> axum::response::Response {
+        let (status_code, body) = match self {
+            Self::InvalidPassword(reason) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Invalid password: {reason}"),
+            ),
+            Self::InvalidLink => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Password reset link is invalid or has expired".to_string(),
+            ),
+            Self::Other => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
+        };
+
+        (status_code, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestPasswordResetParams {
+    username_or_email: UsernameOrEmail,
+}
+
+#[derive(Debug, Serialize)]
+pub enum RequestPasswordResetError {
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordParams {
+    token: String,
+    password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub enum ResetPasswordError {
+    InvalidPassword(&'static str),
+    InvalidLink,
+    Other,
+}
@@ -1,15 +1,17 @@
 use axum::http::StatusCode;
 use axum::{response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
-use sqlx::Row;
+use sqlx::{mysql::MySqlRow, Row};
 
 use crate::common::state::Db;
 use crate::preferences::Preferences;
 
-use super::AuthToken;
+use super::password::{hash_password, verify_password, PasswordVerification, StoredPasswordHash};
+use super::{AuthToken, OptionalAuthToken};
 
 pub async fn sign_in(
     db: Db,
+    OptionalAuthToken(guest): OptionalAuthToken,
     Json(SignInParams {
         username_or_email,
         password,
@@ -17,33 +19,67 @@ pub async fn sign_in(
 ) -> Result<(AuthToken, Json<SignInResponse>), SignInError> {
     let row = match username_or_email {
         UsernameOrEmail::Email(email) => {
-            sqlx::query("SELECT * FROM user WHERE email = ?")
+            sqlx::query("SELECT * FROM user WHERE email = ? AND NOT is_guest")
                 .bind(email.to_owned())
                 .fetch_one(&db)
                 .await?
         }
         UsernameOrEmail::Username(username) => {
-            sqlx::query("SELECT * FROM user WHERE username = ?")
+            sqlx::query("SELECT * FROM user WHERE username = ? AND NOT is_guest")
                 .bind(username.to_owned())
                 .fetch_one(&db)
                 .await?
         }
     };
 
-    let salt: Vec<u8> = row.get("salt");
-    let password_hash: Vec<u8> = row.get("password_hash");
-    let preferences: String = row.get("preferences");
-    let preferences = Preferences::from(preferences);
+    let stored_password_hash = StoredPasswordHash {
+        salt: row.get("salt"),
+        password_hash: match row.get("password_hash") {
+            Some(password_hash) => password_hash,
+            None => return Err(SignInError::InvalidSignInParams),
+        },
+    };
 
-    if super::password_hash(&password, &salt) != password_hash {
-        return Err(SignInError::InvalidSignInParams);
+    match verify_password(password.to_owned(), stored_password_hash).await {
+        PasswordVerification::Incorrect => return Err(SignInError::InvalidSignInParams),
+        PasswordVerification::Correct => {}
+        // Outdated hashes, such as legacy SHA-256 ones, are replaced now that the password is known.
+        PasswordVerification::CorrectNeedsRehash => {
+            let user_id: u32 = row.get("id");
+            sqlx::query("UPDATE user SET salt = NULL, password_hash = ? WHERE id = ?")
+                .bind(hash_password(password).await)
+                .bind(user_id)
+                .execute(&db)
+                .await?;
+        }
     }
 
+    complete_sign_in(&db, guest.and_then(|guest| guest.guest_id()), row).await
+}
+
+/// Signs in as the user in the given row, once they have proven who they are.
+pub(super) async fn complete_sign_in(
+    db: &Db,
+    guest_id: Option<u32>,
+    row: MySqlRow,
+) -> Result<(AuthToken, Json<SignInResponse>), SignInError> {
+    let preferences: String = row.get("preferences");
+    let preferences = Preferences::from(preferences);
+
     let user_id = row.get("id");
     let username: String = row.get("username");
     let email: String = row.get("email");
 
-    let auth_token: AuthToken = AuthToken::new(user_id, &username, &email);
+    // A guest signing in has everything associated with their guest account carried over.
+    if let Some(guest_id) = guest_id.filter(|guest_id| *guest_id != user_id) {
+        sqlx::query("CALL merge_guest (?, ?)")
+            .bind(guest_id)
+            .bind(user_id)
+            .execute(db)
+            .await?;
+    }
+
+    let auth_token = AuthToken::new(db, user_id, &username, &email).await?;
     Ok((
         auth_token,
         Json(SignInResponse {
@@ -71,6 +107,10 @@ impl IntoResponse for SignInError {
                 StatusCode::UNPROCESSABLE_ENTITY,
                 "Invalid username/email/password",
             ),
+            Self::InvalidSignInLink => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Sign in link is invalid or has expired",
+            ),
             Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
         };
 
@@ -96,6 +136,7 @@ pub struct SignInResponse {
 #[derive(Debug)]
 pub enum SignInError {
     InvalidSignInParams,
+    InvalidSignInLink,
     Other,
 }
 
@@ -0,0 +1,111 @@
+use axum::http::StatusCode;
+use axum::{response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+use super::email_token::{
+    consume_email_link, generate_link_token, issue_email_token, EmailTokenError, EmailTokenPurpose,
+};
+use super::sign_in::{complete_sign_in, SignInError, SignInResponse};
+use super::{AuthToken, OptionalAuthToken};
+use crate::common::email::{send_email, FRONTEND_URL};
+use crate::common::state::{Db, Mailer};
+
+pub async fn send_sign_in_link(
+    db: Db,
+    mailer: Mailer,
+    Json(SendSignInLinkParams { email }): Json<SendSignInLinkParams>,
+) -> Result<Json<()>, SendSignInLinkError> {
+    let account_exists: bool =
+        sqlx::query_scalar("SELECT EXISTS(SELECT * FROM user WHERE email = ? AND NOT is_guest)")
+            .bind(&email)
+            .fetch_one(&db)
+            .await?;
+
+    // Whether an account exists isn't revealed to whoever asked for the link.
+    if !account_exists {
+        return Ok(Json(()));
+    }
+
+    // The email is sent in the background, so that how long the request takes doesn't reveal it
+    // either.
+    tokio::spawn(async move {
+        let token = generate_link_token();
+        // Only existing accounts are rate limited, so requests over the limit are dropped without
+        // saying so as well.
+        match issue_email_token(&db, EmailTokenPurpose::SignIn, &email, &token).await {
+            Ok(()) => {}
+            Err(EmailTokenError::TooManyRequests) => return,
+            Err(EmailTokenError::Other) => {
+                tracing::error!("Failed to issue sign in token");
+                return;
+            }
+        }
+
+        if let Err(error) = send_email(
+            &mailer,
+            &email,
+            "Sign in link",
+            format!(
+                "Use the following link to sign in: {}/signin-link?token={token}\nIt expires in 15 minutes and can only be used once. If you didn't ask to sign in, you can ignore this email.",
+                *FRONTEND_URL
+            ),
+        )
+        .await
+        {
+            tracing::error!("Failed to send sign in email: {error}");
+        }
+    });
+
+    Ok(Json(()))
+}
+
+pub async fn sign_in_with_link(
+    db: Db,
+    OptionalAuthToken(guest): OptionalAuthToken,
+    Json(SignInWithLinkParams { token }): Json<SignInWithLinkParams>,
+) -> Result<(AuthToken, Json<SignInResponse>), SignInError> {
+    let mut tx = db.begin().await?;
+    let email = consume_email_link(&mut tx, EmailTokenPurpose::SignIn, &token)
+        .await?
+        .ok_or(SignInError::InvalidSignInLink)?;
+    let row = sqlx::query("SELECT * FROM user WHERE email = ? AND NOT is_guest")
+        .bind(email)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(SignInError::InvalidSignInLink)?;
+    tx.commit().await?;
+
+    complete_sign_in(&db, guest.and_then(|guest| guest.guest_id()), row).await
+}
+
+impl From<sqlx::Error> for SendSignInLinkError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for SendSignInLinkError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendSignInLinkParams {
+    email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub enum SendSignInLinkError {
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignInWithLinkParams {
+    token: String,
+}
@@ -1,18 +1,56 @@
 use axum::http::StatusCode;
 use axum::{response::IntoResponse, Json};
-use once_cell::sync::Lazy;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use super::AuthToken;
-use crate::auth::password_hash;
-use crate::common::state::Db;
+use super::email_token::{
+    consume_email_token, issue_email_token, EmailTokenError, EmailTokenPurpose,
+};
+use super::password::hash_password;
+use super::{AuthToken, OptionalAuthToken};
+use crate::common::email::send_email;
+use crate::common::state::{Db, Mailer};
 use crate::preferences::Preferences;
 
-static VERIFICATION_CODE: Lazy<String> =
-    Lazy::new(|| std::env::var("VERIFICATION_CODE").expect("VERIFICATION_CODE must be set"));
+pub async fn send_verification_code(
+    db: Db,
+    mailer: Mailer,
+    Json(SendVerificationCodeParams { email }): Json<SendVerificationCodeParams>,
+) -> Result<Json<()>, SendVerificationCodeError> {
+    validation::validate_email(&email).map_err(|_| SendVerificationCodeError::InvalidEmail)?;
+
+    let email_taken: bool = sqlx::query_scalar("SELECT EXISTS(SELECT * FROM user WHERE email = ?)")
+        .bind(&email)
+        .fetch_one(&db)
+        .await?;
+    if email_taken {
+        return Err(SendVerificationCodeError::EmailTaken);
+    }
+
+    let verification_code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+    issue_email_token(
+        &db,
+        EmailTokenPurpose::Verification,
+        &email,
+        &verification_code,
+    )
+    .await?;
+
+    send_email(
+        &mailer,
+        &email,
+        "Verification code",
+        format!("Your verification code is {verification_code}. It expires in 10 minutes."),
+    )
+    .await
+    .map_err(|_| SendVerificationCodeError::Other)?;
+
+    Ok(Json(()))
+}
 
 pub async fn sign_up(
     db: Db,
+    OptionalAuthToken(guest): OptionalAuthToken,
     Json(SignUpParams {
         username,
         email,
@@ -26,29 +64,99 @@ pub async fn sign_up(
     validate_email(&email)?;
     validate_password(&password)?;
 
-    // TODO: Send verification code via email.
-    if verification_code != *VERIFICATION_CODE {
+    // The verification code is only used up if the account is actually created.
+    let mut tx = db.begin().await?;
+    if !consume_email_token(
+        &mut tx,
+        EmailTokenPurpose::Verification,
+        &email,
+        &verification_code,
+    )
+    .await?
+    {
+        tx.commit().await?;
         return Err(SignUpError::IncorrectVerificationCode);
     }
 
-    // TODO: Check that quality of randomness here is sufficient.
-    let salt: Vec<u8> = (0..32).map(|_| rand::random()).collect();
-    let password_hash = password_hash(&password, &salt);
+    let password_hash = hash_password(password).await;
     let preferences = preferences.to_string();
 
-    let user_id = sqlx::query(
-            "INSERT INTO user (username, email, salt, password_hash, preferences) VALUES (?, ?, ?, ?, ?)")
-            .bind(username.to_owned())
-            .bind(email.to_owned())
-            .bind(salt)
-            .bind(password_hash)
-            .bind(preferences)
-            .execute(&db).await?.last_insert_id() as u32;
+    // A guest signing up has their guest account turned into an actual account, so that everything
+    // associated with it carries over.
+    let guest = guest.filter(|guest| guest.is_guest);
+    let guest_id = guest.as_ref().map(|guest| guest.user_id);
+    let upgraded_guest = match guest_id {
+        Some(guest_id) => {
+            sqlx::query(
+                "UPDATE user SET username = ?, email = ?, salt = NULL, password_hash = ?, preferences = ?, is_guest = FALSE WHERE id = ? AND is_guest")
+                .bind(username.to_owned())
+                .bind(email.to_owned())
+                .bind(&password_hash)
+                .bind(&preferences)
+                .bind(guest_id)
+                .execute(&mut *tx).await?.rows_affected() > 0
+        }
+        None => false,
+    };
 
-    let auth_token = AuthToken::new(user_id, &username, &email);
+    let user_id = match guest_id {
+        Some(guest_id) if upgraded_guest => guest_id,
+        _ => sqlx::query(
+            "INSERT INTO user (username, email, password_hash, preferences) VALUES (?, ?, ?, ?)",
+        )
+        .bind(username.to_owned())
+        .bind(email.to_owned())
+        .bind(password_hash)
+        .bind(preferences)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id() as u32,
+    };
+
+    // The guest's session is replaced by one for the actual account.
+    if let Some(guest) = guest.filter(|_| upgraded_guest) {
+        sqlx::query("DELETE FROM session WHERE id = ?")
+            .bind(guest.session_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    let auth_token = AuthToken::new(&db, user_id, &username, &email).await?;
     Ok((auth_token, Json(SignUpResponse { username, email })))
 }
 
+impl From<sqlx::Error> for SendVerificationCodeError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl From<EmailTokenError> for SendVerificationCodeError {
+    fn from(error: EmailTokenError) -> Self {
+        match error {
+            EmailTokenError::TooManyRequests => Self::TooManyRequests,
+            EmailTokenError::Other => Self::Other,
+        }
+    }
+}
+
+impl IntoResponse for SendVerificationCodeError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::InvalidEmail => (StatusCode::UNPROCESSABLE_ENTITY, "Invalid email"),
+            Self::EmailTaken => (StatusCode::UNPROCESSABLE_ENTITY, "Email already in use"),
+            Self::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many verification codes requested, try again later",
+            ),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
 impl From<sqlx::Error> for SignUpError {
     fn from(error: sqlx::Error) -> Self {
         match error.as_database_error() {
@@ -107,6 +215,20 @@ impl IntoResponse for SignUpError {
     }
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendVerificationCodeParams {
+    email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub enum SendVerificationCodeError {
+    InvalidEmail,
+    EmailTaken,
+    TooManyRequests,
+    Other,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SignUpParams {
@@ -134,11 +256,12 @@ pub enum SignUpError {
     Other,
 }
 
-mod validation {
+pub(crate) mod validation {
     use lazy_static::lazy_static;
     use regex::Regex;
 
     use super::SignUpError;
+    use crate::auth::guest::GUEST_USERNAME_PREFIX;
 
     pub fn validate_username(username: &str) -> Result<(), SignUpError> {
         if username.len() < 6 {
@@ -156,6 +279,10 @@ mod validation {
             Err(SignUpError::InvalidUsername(
                 "Username must consist of only letters, digits, underscores and periods",
             ))
+        } else if username.starts_with(GUEST_USERNAME_PREFIX) {
+            Err(SignUpError::InvalidUsername(
+                "Username must not start with \"guest_\"",
+            ))
         } else {
             Ok(())
         }
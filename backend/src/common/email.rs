@@ -0,0 +1,26 @@
+use lettre::{AsyncTransport, Message};
+use once_cell::sync::Lazy;
+
+use super::state::Mailer;
+
+static EMAIL_FROM: Lazy<String> =
+    Lazy::new(|| std::env::var("EMAIL_FROM").expect("EMAIL_FROM must be set"));
+
+/// The base URL of the frontend, for links sent in emails.
+pub static FRONTEND_URL: Lazy<String> =
+    Lazy::new(|| std::env::var("FRONTEND_URL").expect("FRONTEND_URL must be set"));
+
+pub async fn send_email(
+    mailer: &Mailer,
+    to: &str,
+    subject: &str,
+    body: String,
+) -> Result<(), anyhow::Error> {
+    let email = Message::builder()
+        .from(EMAIL_FROM.parse()?)
+        .to(to.parse()?)
+        .subject(subject)
+        .body(body)?;
+    mailer.send(email).await?;
+    Ok(())
+}
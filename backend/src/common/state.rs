@@ -5,6 +5,7 @@ use lettre::{transport::smtp::authentication::Credentials, AsyncSmtpTransport, T
 use sqlx::mysql::MySqlPoolOptions;
 
 use crate::typing_race::{
+    chat::WordFilter,
     room::{spawn_room_manager, RoomMgr},
     spawn_matchmaking_service, Mms,
 };
@@ -22,11 +23,13 @@ pub struct AppState {
 
 impl AppState {
     pub async fn new() -> Self {
+        let db = Self::get_db().await;
+        let word_filter = WordFilter::from_env();
         Self {
-            db: Self::get_db().await,
+            db: db.clone(),
             mailer: Self::get_mailer(),
-            matchmaking: spawn_matchmaking_service(),
-            room_mgr: spawn_room_manager(),
+            matchmaking: spawn_matchmaking_service(db.clone(), word_filter.clone()),
+            room_mgr: spawn_room_manager(db, word_filter),
         }
     }
 
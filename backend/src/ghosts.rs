@@ -0,0 +1,152 @@
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{MySqlConnection, Row};
+
+use crate::{
+    auth::AuthToken,
+    common::state::Db,
+    typing_test::{replay::Timeline, SeededTestParams},
+};
+
+/// Ghosts expire this long after the test was completed, unless they are of a personal best.
+const GHOST_VALIDITY_DURATION: Duration = Duration::hours(24);
+
+pub async fn create_ghost_link(
+    db: Db,
+    auth_token: AuthToken,
+    Json(CreateGhostLinkParams { result_id }): Json<CreateGhostLinkParams>,
+) -> Result<Json<CreateGhostLinkResponse>, GhostError> {
+    let ghost_id: String = sqlx::query_scalar(
+        "SELECT ghost.id FROM ghost JOIN result ON result.id = ghost.result_id WHERE ghost.result_id = ? AND result.user_id = ? AND (ghost.expiry_timestamp IS NULL OR ghost.expiry_timestamp > NOW())",
+    )
+    .bind(result_id)
+    .bind(auth_token.user_id)
+    .fetch_one(&db)
+    .await?;
+
+    Ok(Json(CreateGhostLinkResponse { ghost_id }))
+}
+
+pub async fn get_ghost(
+    db: Db,
+    Query(GetGhostParams { ghost_id }): Query<GetGhostParams>,
+) -> Result<Json<Ghost>, GhostError> {
+    let ghost = load_ghost(&db, &ghost_id)
+        .await?
+        .ok_or(GhostError::NotFound)?;
+    Ok(Json(ghost))
+}
+
+/// Loads the ghost with the given id, provided it hasn't expired.
+pub async fn load_ghost(db: &Db, ghost_id: &str) -> Result<Option<Ghost>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT user.username, ghost.test_params, ghost.timeline FROM ghost JOIN result ON result.id = ghost.result_id JOIN user ON user.id = result.user_id WHERE ghost.id = ? AND (ghost.expiry_timestamp IS NULL OR ghost.expiry_timestamp > NOW())",
+    )
+    .bind(ghost_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| {
+        let test_params: String = row.get("test_params");
+        let timeline: String = row.get("timeline");
+        Ghost {
+            username: row.get("username"),
+            test_params: serde_json::from_str(&test_params).expect("Well-formed serialization"),
+            timeline: serde_json::from_str(&timeline).expect("Well-formed serialization"),
+        }
+    }))
+}
+
+/// Stores the ghost of a freshly inserted result. A ghost of a personal best is kept until it is
+/// beaten, at which point it expires like any other ghost.
+pub async fn store_ghost(
+    conn: &mut MySqlConnection,
+    result_id: u32,
+    test_params: &SeededTestParams,
+    timeline: &Timeline,
+    test_completed_timestamp: DateTime<Utc>,
+    is_personal_best: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM ghost WHERE expiry_timestamp < NOW()")
+        .execute(&mut *conn)
+        .await?;
+
+    let expiry_timestamp = if is_personal_best {
+        sqlx::query(
+            "UPDATE ghost JOIN result ON result.id = ghost.result_id JOIN result AS new_result ON new_result.id = ? SET ghost.expiry_timestamp = result.test_completed_timestamp + INTERVAL 1 DAY WHERE ghost.expiry_timestamp IS NULL AND result.user_id = new_result.user_id AND result.test_params = new_result.test_params",
+        )
+        .bind(result_id)
+        .execute(&mut *conn)
+        .await?;
+        None
+    } else {
+        Some(test_completed_timestamp + GHOST_VALIDITY_DURATION)
+    };
+
+    let ghost_id = uuid::Uuid::new_v4().simple().to_string();
+    sqlx::query(
+        "INSERT INTO ghost (id, result_id, test_params, timeline, expiry_timestamp) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(ghost_id)
+    .bind(result_id)
+    .bind(serde_json::to_string(test_params).expect("no error"))
+    .bind(serde_json::to_string(timeline).expect("no error"))
+    .bind(expiry_timestamp)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+impl From<sqlx::Error> for GhostError {
+    fn from(error: sqlx::Error) -> Self {
+        if let sqlx::Error::RowNotFound = error {
+            Self::NotFound
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl IntoResponse for GhostError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::NotFound => (StatusCode::NOT_FOUND, "Ghost not found or expired"),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGhostLinkParams {
+    result_id: u32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGhostLinkResponse {
+    ghost_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetGhostParams {
+    ghost_id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ghost {
+    pub username: String,
+    pub test_params: SeededTestParams,
+    pub timeline: Timeline,
+}
+
+#[derive(Debug, Serialize)]
+pub enum GhostError {
+    NotFound,
+    Other,
+}
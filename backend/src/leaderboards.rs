@@ -0,0 +1,316 @@
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, Json};
+use chrono::{serde::ts_milliseconds, DateTime, Datelike, Duration, NaiveTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::{mysql::MySqlRow, Row};
+
+use crate::{
+    auth::OptionalAuthToken, common::state::Db, preferences::TypingTestMode,
+    typing_test::RandomTestParams,
+};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 100;
+
+/// The test params for which leaderboards are maintained.
+static LEADERBOARD_TEST_PARAMS: Lazy<Vec<RandomTestParams>> = Lazy::new(|| {
+    let language = || "english".to_owned();
+    vec![
+        RandomTestParams::Time {
+            language: language(),
+            duration: 15,
+        },
+        RandomTestParams::Time {
+            language: language(),
+            duration: 60,
+        },
+        RandomTestParams::Words {
+            language: language(),
+            length: 10,
+        },
+        RandomTestParams::Words {
+            language: language(),
+            length: 25,
+        },
+    ]
+});
+
+// Each user is ranked by their best verified result in the period. Ties are broken by accuracy and
+// then by whoever got there first. Guests aren't ranked.
+const RANKED_RESULTS: &str = "WITH best AS (
+    SELECT user_id, wpm, raw_wpm, accuracy, test_completed_timestamp,
+        ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY wpm DESC, accuracy DESC, test_completed_timestamp ASC) AS n
+    FROM result JOIN user ON user.id = result.user_id
+    WHERE result.verified AND NOT user.is_guest AND result.test_params = ? AND result.test_completed_timestamp >= ?
+), ranked AS (
+    SELECT user_id, wpm, raw_wpm, accuracy, test_completed_timestamp,
+        CAST(RANK() OVER (ORDER BY wpm DESC, accuracy DESC, test_completed_timestamp ASC) AS SIGNED) AS `rank`
+    FROM best
+    WHERE n = 1
+)
+SELECT `rank`, username, wpm, raw_wpm, accuracy, test_completed_timestamp FROM ranked JOIN user ON user.id = ranked.user_id";
+
+pub async fn get_leaderboard_test_params() -> Json<GetLeaderboardTestParamsResponse> {
+    Json(GetLeaderboardTestParamsResponse {
+        test_params: &LEADERBOARD_TEST_PARAMS,
+    })
+}
+
+pub async fn get_leaderboard(
+    db: Db,
+    OptionalAuthToken(auth_token): OptionalAuthToken,
+    Query(params): Query<GetLeaderboardParams>,
+) -> Result<Json<GetLeaderboardResponse>, GetLeaderboardError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(GetLeaderboardError::InvalidLimit);
+    }
+
+    let test_params = params
+        .test_params()
+        .filter(|test_params| LEADERBOARD_TEST_PARAMS.contains(test_params))
+        .ok_or(GetLeaderboardError::NoSuchLeaderboard)?;
+    let test_params = serde_json::to_string(&test_params).expect("no error");
+    let period_start = params.period.start(Utc::now());
+
+    let entries = sqlx::query(&format!(
+        "{RANKED_RESULTS} ORDER BY `rank`, user_id LIMIT ?"
+    ))
+    .bind(&test_params)
+    .bind(period_start)
+    .bind(limit)
+    .fetch_all(&db)
+    .await?
+    .into_iter()
+    .map(LeaderboardEntry::try_from)
+    .collect::<Result<_, _>>()?;
+
+    let own_entry = match auth_token {
+        Some(auth_token) => sqlx::query(&format!("{RANKED_RESULTS} WHERE user_id = ?"))
+            .bind(&test_params)
+            .bind(period_start)
+            .bind(auth_token.user_id)
+            .fetch_optional(&db)
+            .await?
+            .map(LeaderboardEntry::try_from)
+            .transpose()?,
+        None => None,
+    };
+
+    Ok(Json(GetLeaderboardResponse { entries, own_entry }))
+}
+
+impl GetLeaderboardParams {
+    fn test_params(&self) -> Option<RandomTestParams> {
+        let language = self.language.to_owned()?;
+        match self.mode {
+            TypingTestMode::Words => Some(RandomTestParams::Words {
+                language,
+                length: self.length?,
+            }),
+            TypingTestMode::Time => Some(RandomTestParams::Time {
+                language,
+                duration: self.duration?,
+            }),
+            TypingTestMode::Quote => None,
+        }
+    }
+}
+
+impl LeaderboardPeriod {
+    fn start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.date_naive();
+        match self {
+            Self::AllTime => DateTime::UNIX_EPOCH,
+            Self::Daily => today.and_time(NaiveTime::MIN).and_utc(),
+            Self::Weekly => {
+                let days_since_monday = today.weekday().num_days_from_monday() as i64;
+                (today - Duration::days(days_since_monday))
+                    .and_time(NaiveTime::MIN)
+                    .and_utc()
+            }
+        }
+    }
+}
+
+impl TryFrom<MySqlRow> for LeaderboardEntry {
+    type Error = sqlx::Error;
+
+    fn try_from(row: MySqlRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            rank: row.try_get::<i64, _>("rank")?,
+            username: row.try_get("username")?,
+            wpm: row.try_get("wpm")?,
+            raw_wpm: row.try_get("raw_wpm")?,
+            accuracy: row.try_get("accuracy")?,
+            test_completed_timestamp: row.try_get("test_completed_timestamp")?,
+        })
+    }
+}
+
+impl From<sqlx::Error> for GetLeaderboardError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for GetLeaderboardError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::NoSuchLeaderboard => (StatusCode::NOT_FOUND, "No such leaderboard".to_string()),
+            Self::InvalidLimit => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Limit should be between 1 and {MAX_LIMIT}"),
+            ),
+            Self::Other => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetLeaderboardTestParamsResponse {
+    test_params: &'static [RandomTestParams],
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetLeaderboardParams {
+    mode: TypingTestMode,
+    language: Option<String>,
+    length: Option<u32>,
+    duration: Option<u32>,
+    period: LeaderboardPeriod,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LeaderboardPeriod {
+    AllTime,
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetLeaderboardResponse {
+    entries: Vec<LeaderboardEntry>,
+    own_entry: Option<LeaderboardEntry>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    rank: i64,
+    username: String,
+    wpm: f32,
+    raw_wpm: f32,
+    accuracy: f32,
+
+    #[serde(with = "ts_milliseconds")]
+    test_completed_timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub enum GetLeaderboardError {
+    NoSuchLeaderboard,
+    InvalidLimit,
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    fn params(
+        mode: TypingTestMode,
+        length: Option<u32>,
+        duration: Option<u32>,
+    ) -> GetLeaderboardParams {
+        GetLeaderboardParams {
+            mode,
+            language: Some("english".to_owned()),
+            length,
+            duration,
+            period: LeaderboardPeriod::AllTime,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn starts_daily_leaderboards_at_midnight_utc() {
+        let start = at("2024-03-14T00:00:00Z");
+        for now in [
+            "2024-03-14T00:00:00Z",
+            "2024-03-14T13:37:00Z",
+            "2024-03-14T23:59:59.999Z",
+        ] {
+            assert_eq!(LeaderboardPeriod::Daily.start(at(now)), start);
+        }
+    }
+
+    #[test]
+    fn starts_weekly_leaderboards_on_monday() {
+        // 2024-03-11 was a Monday.
+        let start = at("2024-03-11T00:00:00Z");
+        for now in [
+            "2024-03-11T00:00:00Z",
+            "2024-03-14T13:37:00Z",
+            "2024-03-17T23:59:59Z",
+        ] {
+            assert_eq!(LeaderboardPeriod::Weekly.start(at(now)), start);
+        }
+        assert_eq!(
+            LeaderboardPeriod::Weekly.start(at("2024-03-18T00:00:00Z")),
+            at("2024-03-18T00:00:00Z"),
+        );
+        // Weeks can span months and years.
+        assert_eq!(
+            LeaderboardPeriod::Weekly.start(at("2025-01-02T12:00:00Z")),
+            at("2024-12-30T00:00:00Z"),
+        );
+    }
+
+    #[test]
+    fn starts_all_time_leaderboards_at_the_epoch() {
+        assert_eq!(
+            LeaderboardPeriod::AllTime.start(at("2024-03-14T13:37:00Z")),
+            DateTime::UNIX_EPOCH,
+        );
+    }
+
+    #[test]
+    fn picks_the_leaderboard_for_the_test() {
+        assert_eq!(
+            params(TypingTestMode::Time, None, Some(15)).test_params(),
+            Some(RandomTestParams::Time {
+                language: "english".to_owned(),
+                duration: 15,
+            }),
+        );
+        assert_eq!(
+            params(TypingTestMode::Words, Some(10), None).test_params(),
+            Some(RandomTestParams::Words {
+                language: "english".to_owned(),
+                length: 10,
+            }),
+        );
+        assert_eq!(
+            params(TypingTestMode::Words, None, Some(15)).test_params(),
+            None
+        );
+        assert_eq!(
+            params(TypingTestMode::Quote, None, None).test_params(),
+            None
+        );
+    }
+}
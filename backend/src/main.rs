@@ -1,12 +1,10 @@
-use axum::handler::Handler;
 use axum::http::header::CONTENT_TYPE;
 use axum::http::{HeaderValue, Method};
-use axum::middleware::map_response;
+use axum::middleware::map_response_with_state;
 use axum::routing::{get, post};
 use axum::Router;
 use common::state::AppState;
 use dotenv::dotenv;
-use lettre::Message;
 use tower_http::cors::CorsLayer;
 
 mod auth;
@@ -21,22 +19,17 @@ use results::*;
 mod preferences;
 use preferences::*;
 
+mod leaderboards;
+use leaderboards::*;
+
+mod ghosts;
+use ghosts::*;
+
 mod experimental;
 use experimental::*;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-#[allow(dead_code)]
-fn get_email() -> Result<Message, anyhow::Error> {
-    let email = Message::builder()
-        .from("Joseph <epicjoe128@gmail.com>".parse()?)
-        .reply_to("Joseph <epicjoe128@gmail.com>".parse()?)
-        .to("Joseph <epicjoe128@gmail.com>".parse()?)
-        .subject("Test email")
-        .body(String::from("Testing this"))?;
-    Ok(email)
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
@@ -49,22 +42,44 @@ async fn main() {
 
     dotenv().ok();
 
+    let state = AppState::new().await;
+
     let app = Router::new()
         .route("/signup", post(auth::sign_up))
+        .route("/signup/code", post(auth::send_verification_code))
         .route("/signin", post(auth::sign_in))
-        .route(
-            "/current",
-            get(auth::current_user.layer(map_response(auth::refresh_auth_token))),
-        )
-        .route("/logout", get(auth::log_out))
+        .route("/signin/link", post(auth::send_sign_in_link))
+        .route("/signin/link/confirm", post(auth::sign_in_with_link))
+        .route("/guest", post(auth::create_guest))
+        .route("/oidc/:provider", get(auth::start_oidc_sign_in))
+        .route("/oidc/:provider/callback", get(auth::oidc_callback))
+        .route("/current", get(auth::current_user))
+        .route("/logout", post(auth::log_out))
+        .route("/logout/everywhere", post(auth::log_out_everywhere))
+        .route("/password/reset", post(auth::request_password_reset))
+        .route("/password/reset/confirm", post(auth::reset_password))
         .route("/result", get(get_results).post(post_result))
         .route("/stat", get(get_stats))
         .route("/prefs", post(update_preferences))
+        .route("/leaderboard", get(get_leaderboard))
+        .route("/leaderboards", get(get_leaderboard_test_params))
+        .route("/ghost", get(get_ghost).post(create_ghost_link))
         .route("/race", get(typing_race::join_matchmaking))
+        .route("/race/history", get(typing_race::history::get_races))
+        .route("/race/history/:id", get(typing_race::history::get_race))
+        .route("/race/rating", get(typing_race::rating::get_ratings))
+        .route("/room", get(typing_race::room::get_rooms))
+        .route("/room/watch", get(typing_race::room::watch_rooms))
         .route("/room/create", post(typing_race::room::create_room))
         .route("/room/join", get(typing_race::room::join_room))
+        .route("/room/spectate", get(typing_race::room::spectate_room))
+        .route("/room/ghost", post(typing_race::room::add_ghost_to_room))
         .route("/experimental", get(experimental))
-        .with_state(AppState::new().await)
+        .layer(map_response_with_state(
+            state.clone(),
+            auth::refresh_auth_token,
+        ))
+        .with_state(state)
         .layer(
             CorsLayer::new()
                 .allow_origin(
@@ -52,7 +52,7 @@ pub enum Language {
     English450k,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum QuoteModeLength {
     Short,
@@ -62,6 +62,21 @@ pub enum QuoteModeLength {
     All,
 }
 
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            current_mode: TypingTestMode::Words,
+            words_mode_length: 25,
+            time_mode_duration: 30,
+            language: Language::English,
+            quote_mode_length: QuoteModeLength::Medium,
+            show_all_lines: false,
+            allow_skipping_words: true,
+            allow_backspacing_words: true,
+        }
+    }
+}
+
 impl From<String> for Preferences {
     fn from(value: String) -> Self {
         serde_json::from_str(&value).expect("no error")
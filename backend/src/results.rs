@@ -1,11 +1,21 @@
 use axum::{extract::Query, http::StatusCode, response::IntoResponse, Json};
-use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use chrono::{
+    serde::{ts_milliseconds, ts_milliseconds_option},
+    DateTime, Utc,
+};
 use serde::{Deserialize, Serialize};
+use sqlx::{mysql::MySql, FromRow, QueryBuilder, Row};
 
 use crate::{
     auth::AuthToken,
     common::{error::AppError, state::Db},
-    typing_test::RandomTestParams,
+    ghosts::store_ghost,
+    preferences::{QuoteModeLength, TypingTestMode},
+    typing_test::{
+        replay::{Evaluation, ReplayError, Timeline},
+        stat::Stats,
+        RandomTestParams, Replay,
+    },
 };
 
 pub async fn post_result(
@@ -17,56 +27,136 @@ pub async fn post_result(
         wpm,
         raw_wpm,
         accuracy,
+        replay,
     }): Json<TestResult>,
-) -> Result<Json<()>, PostResultError> {
+) -> Result<Json<PostResultResponse>, PostResultError> {
+    let ghost = match replay {
+        Some(replay) => {
+            let timeline = verify_result(&test_params, wpm, raw_wpm, accuracy, &replay)?;
+            Some((replay.test_params, timeline))
+        }
+        None => None,
+    };
+    let verified = ghost.is_some();
+
     let test_params = serde_json::to_string(&test_params)?;
 
-    sqlx::query!(
-        "CALL insert_result (?, ?, ?, ?, ?, ?)",
-        auth_token.user_id,
-        test_params,
-        test_completed_timestamp,
-        wpm,
-        raw_wpm,
-        accuracy,
-    )
-    .execute(&db)
-    .await?;
+    let mut tx = db.begin().await?;
+
+    let row = sqlx::query("CALL insert_result (?, ?, ?, ?, ?, ?, ?)")
+        .bind(auth_token.user_id)
+        .bind(test_params)
+        .bind(test_completed_timestamp)
+        .bind(wpm)
+        .bind(raw_wpm)
+        .bind(accuracy)
+        .bind(verified)
+        .fetch_one(&mut *tx)
+        .await?;
+    let result_id: u32 = row.get("id");
+    let is_personal_best: bool = row.get("is_personal_best");
+
+    if let Some((seeded_test_params, timeline)) = ghost {
+        store_ghost(
+            &mut tx,
+            result_id,
+            &seeded_test_params,
+            &timeline,
+            test_completed_timestamp,
+            is_personal_best,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(PostResultResponse { result_id }))
+}
+
+// Recomputes the stats of the result from its replay and checks that they match the claimed ones.
+// Returns the progress timeline of the replay, for use as a ghost.
+fn verify_result(
+    test_params: &RandomTestParams,
+    wpm: f32,
+    raw_wpm: f32,
+    accuracy: f32,
+    replay: &Replay,
+) -> Result<Timeline, PostResultError> {
+    if RandomTestParams::from(replay.test_params.clone()) != *test_params {
+        return Err(PostResultError::InvalidResult(
+            "Test params don't match those of the replay",
+        ));
+    }
+
+    let Evaluation { stats, timeline } = replay.evaluate()?;
+    let Stats {
+        wpm: actual_wpm,
+        raw_wpm: actual_raw_wpm,
+        accuracy: actual_accuracy,
+    } = stats;
+
+    // The claimed stats are computed from the client's own measurement of the test duration, which
+    // may be slightly off from the keystroke timestamps.
+    let approx_eq =
+        |claimed: f32, actual: f32| (claimed - actual).abs() <= (actual * 0.01).max(0.5);
+    if !approx_eq(wpm, actual_wpm)
+        || !approx_eq(raw_wpm, actual_raw_wpm)
+        || !approx_eq(accuracy, actual_accuracy)
+    {
+        return Err(PostResultError::InvalidResult(
+            "Stats don't match those of the replay",
+        ));
+    }
 
-    Ok(Json(()))
+    Ok(timeline)
 }
 
 pub async fn get_results(
     db: Db,
     auth_token: AuthToken,
-    Query(GetResultsParams { cursor, limit }): Query<GetResultsParams>,
+    Query(params): Query<GetResultsParams>,
 ) -> Result<Json<GetResultsResponse>, GetResultsError> {
-    if limit == 0 {
+    if params.limit == 0 {
         return Err(GetResultsError::NonPositiveLimit);
     }
 
+    // Results are most recently posted first unless asked otherwise.
+    let sort_column = params.sort_by.as_ref().map_or("id", SortKey::column);
+    let (comparison, direction) = match params.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+
+    let mut query = QueryBuilder::<MySql>::new(
+        "SELECT id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy FROM result WHERE user_id = ",
+    );
+    query.push_bind(auth_token.user_id);
+    params.push_filters(&mut query);
+
+    // Results are paged by their position in the sort order, with the id as a tie-breaker, so that
+    // pages stay consistent regardless of what the results are sorted by.
+    if let Some(cursor) = params.cursor {
+        query
+            .push(format!(" AND ({sort_column}, id) {comparison} (SELECT {sort_column}, id FROM result WHERE id = "))
+            .push_bind(cursor)
+            .push(" AND user_id = ")
+            .push_bind(auth_token.user_id)
+            .push(")");
+    }
+
+    query
+        .push(format!(
+            " ORDER BY {sort_column} {direction}, id {direction} LIMIT "
+        ))
+        .push_bind(params.limit + 1);
+
     let (results_with_id, results_exhausted) = {
-        let mut results_with_id = match cursor {
-            Some(cursor) => sqlx::query_as!(
-                    TestResultWithId,
-                    "SELECT id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy FROM result WHERE user_id = ? AND id < ? ORDER BY id DESC LIMIT ?",
-                    auth_token.user_id,
-                    cursor,
-                    limit + 1,
-                )
-                .fetch_all(&db)
-                .await?,
-            None => sqlx::query_as!(
-                    TestResultWithId,
-                    "SELECT id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy FROM result WHERE user_id = ? ORDER BY id DESC LIMIT ?",
-                    auth_token.user_id,
-                    limit + 1,
-                )
-                .fetch_all(&db)
-                .await?,
-        };
+        let mut results_with_id = query
+            .build_query_as::<TestResultWithId>()
+            .fetch_all(&db)
+            .await?;
 
-        let results_exhausted = results_with_id.len() < (limit + 1) as usize;
+        let results_exhausted = results_with_id.len() < (params.limit + 1) as usize;
         if !results_exhausted {
             results_with_id.pop();
         }
@@ -88,6 +178,62 @@ pub async fn get_results(
     Ok(Json(GetResultsResponse { cursor, results }))
 }
 
+impl GetResultsParams {
+    fn push_filters(&self, query: &mut QueryBuilder<MySql>) {
+        if let Some(mode) = &self.mode {
+            let mode = match mode {
+                TypingTestMode::Words => "words",
+                TypingTestMode::Time => "time",
+                TypingTestMode::Quote => "quote",
+            };
+            query
+                .push(" AND JSON_VALUE(test_params, '$.mode') = ")
+                .push_bind(mode);
+        }
+        if let Some(language) = &self.language {
+            query
+                .push(" AND JSON_VALUE(test_params, '$.params.language') = ")
+                .push_bind(language.to_owned());
+        }
+        // Values extracted from the JSON are strings, so everything is compared as a string.
+        if let Some(length) = self.length {
+            query
+                .push(" AND JSON_VALUE(test_params, '$.mode') = 'words' AND JSON_VALUE(test_params, '$.params.length') = ")
+                .push_bind(length.to_string());
+        }
+        if let Some(duration) = self.duration {
+            query
+                .push(" AND JSON_VALUE(test_params, '$.mode') = 'time' AND JSON_VALUE(test_params, '$.params.duration') = ")
+                .push_bind(duration.to_string());
+        }
+        if let Some(quote_length) = self.quote_length {
+            let quote_length = serde_json::to_value(quote_length).expect("no error");
+            query
+                .push(" AND JSON_VALUE(test_params, '$.mode') = 'quote' AND JSON_VALUE(test_params, '$.params.length') = ")
+                .push_bind(quote_length.as_str().expect("unit variant").to_owned());
+        }
+        if let Some(from) = self.from {
+            query
+                .push(" AND test_completed_timestamp >= ")
+                .push_bind(from);
+        }
+        if let Some(to) = self.to {
+            query.push(" AND test_completed_timestamp < ").push_bind(to);
+        }
+    }
+}
+
+impl SortKey {
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Wpm => "wpm",
+            Self::RawWpm => "raw_wpm",
+            Self::Accuracy => "accuracy",
+            Self::Date => "test_completed_timestamp",
+        }
+    }
+}
+
 pub async fn get_stats(db: Db, auth_token: AuthToken) -> Result<Json<GetStatsResponse>, AppError> {
     let results = sqlx::query!(
         "SELECT test_params, best_wpm, best_raw_wpm, best_accuracy, sum_wpm, sum_raw_wpm, sum_accuracy, n_results FROM stat WHERE user_id = ?",
@@ -120,6 +266,12 @@ impl From<serde_json::Error> for PostResultError {
     }
 }
 
+impl From<ReplayError> for PostResultError {
+    fn from(error: ReplayError) -> Self {
+        Self::InvalidResult(error.reason())
+    }
+}
+
 impl From<sqlx::Error> for PostResultError {
     fn from(error: sqlx::Error) -> Self {
         if let sqlx::Error::Database(error) = error {
@@ -141,6 +293,10 @@ impl IntoResponse for PostResultError {
                 StatusCode::UNPROCESSABLE_ENTITY,
                 "Duplicate result".to_string(),
             ),
+            Self::InvalidResult(reason) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Invalid result: {reason}"),
+            ),
             Self::Other => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal server error".to_string(),
@@ -166,6 +322,7 @@ impl Into<TestResult> for TestResultWithId {
             wpm,
             raw_wpm,
             accuracy,
+            replay: None,
         }
     }
 }
@@ -194,6 +351,38 @@ impl From<sqlx::Error> for GetResultsError {
 pub struct GetResultsParams {
     cursor: Option<u32>,
     limit: u32,
+
+    mode: Option<TypingTestMode>,
+    language: Option<String>,
+    length: Option<u32>,
+    duration: Option<u32>,
+    quote_length: Option<QuoteModeLength>,
+
+    #[serde(default, with = "ts_milliseconds_option")]
+    from: Option<DateTime<Utc>>,
+    #[serde(default, with = "ts_milliseconds_option")]
+    to: Option<DateTime<Utc>>,
+
+    sort_by: Option<SortKey>,
+    #[serde(default)]
+    order: SortOrder,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    Wpm,
+    RawWpm,
+    Accuracy,
+    Date,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
 }
 
 #[derive(Debug, Serialize)]
@@ -210,9 +399,16 @@ pub enum GetResultsError {
     Other,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostResultResponse {
+    result_id: u32,
+}
+
 #[derive(Debug, Serialize)]
 pub enum PostResultError {
     DuplicateResult,
+    InvalidResult(&'static str),
     Other,
 }
 
@@ -232,13 +428,18 @@ pub struct TestResult {
     wpm: f32,
     raw_wpm: f32,
     accuracy: f32,
+
+    /// Present when the client wants the result to be verified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replay: Option<Replay>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct TestResultWithId {
     id: u32,
 
+    #[sqlx(json)]
     test_params: RandomTestParams,
 
     #[serde(with = "ts_milliseconds")]
@@ -266,3 +467,67 @@ impl From<String> for RandomTestParams {
         serde_json::from_str(&test_result_json).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typing_test::{replay::Keystroke, SeededTestParams};
+
+    fn replay() -> Replay {
+        let keystrokes = "One and one and one is three."
+            .chars()
+            .enumerate()
+            .map(|(i, c)| Keystroke(i as u32 * 100, 0, c.to_string()))
+            .collect();
+        Replay {
+            test_params: SeededTestParams::Quote {
+                length: QuoteModeLength::Short,
+                id: 0,
+            },
+            keystrokes,
+        }
+    }
+
+    fn verify(wpm: f32, raw_wpm: f32, accuracy: f32) -> Result<Timeline, PostResultError> {
+        let test_params = RandomTestParams::Quote {
+            length: QuoteModeLength::Short,
+        };
+        verify_result(&test_params, wpm, raw_wpm, accuracy, &replay())
+    }
+
+    #[test]
+    fn accepts_stats_matching_the_replay() {
+        // The replay's stats are 124.29 WPM, 124.29 raw WPM and 100% accuracy.
+        assert!(verify(124.29, 124.29, 100.0).is_ok());
+        assert!(verify(125.0, 123.5, 99.6).is_ok());
+    }
+
+    #[test]
+    fn rejects_stats_not_matching_the_replay() {
+        for (wpm, raw_wpm, accuracy) in [
+            (130.0, 124.29, 100.0),
+            (124.29, 120.0, 100.0),
+            (124.29, 124.29, 95.0),
+        ] {
+            assert!(matches!(
+                verify(wpm, raw_wpm, accuracy),
+                Err(PostResultError::InvalidResult(
+                    "Stats don't match those of the replay"
+                )),
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_replays_of_other_tests() {
+        let test_params = RandomTestParams::Quote {
+            length: QuoteModeLength::Long,
+        };
+        assert!(matches!(
+            verify_result(&test_params, 124.29, 124.29, 100.0, &replay()),
+            Err(PostResultError::InvalidResult(
+                "Test params don't match those of the replay"
+            )),
+        ));
+    }
+}
@@ -1,26 +1,47 @@
-use std::{collections::BTreeMap, fmt::Debug, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    time::{Duration, Instant},
+};
 
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        Query, State, WebSocketUpgrade,
     },
+    http::StatusCode,
     response::IntoResponse,
 };
 use futures::{
     stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt,
+    FutureExt, SinkExt, StreamExt,
 };
 use serde::{Deserialize, Serialize};
-use tokio::{
-    sync::{
-        mpsc::{self, Sender},
-        oneshot,
-    },
-    time::sleep,
+use tokio::sync::{
+    mpsc::{self, Sender},
+    oneshot,
+};
+
+use crate::{
+    auth::AuthToken,
+    common::state::{AppState, Db},
+    preferences::{QuoteModeLength, TypingTestMode},
+    typing_test::{RandomTestParams, SeededTestParams},
 };
 
-use crate::{auth::AuthToken, common::state::AppState, typing_test::Seed};
+pub mod matchmaking;
+use matchmaking::{MatchmakingConfig, MatchmakingQueue};
+
+pub mod chat;
+use chat::{Chat, ChatMessage, WordFilter};
+
+pub mod history;
+use history::{FinishRejection, RaceRecorder};
+
+pub mod rating;
+
+mod referee;
+use rating::update_ratings;
 
 pub mod room;
 
@@ -28,49 +49,165 @@ pub async fn join_matchmaking(
     State(state): State<AppState>,
     auth_token: AuthToken,
     ws: WebSocketUpgrade,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(state, auth_token, socket))
+    Query(params): Query<JoinMatchmakingParams>,
+) -> Result<impl IntoResponse, JoinMatchmakingError> {
+    let test_params = params
+        .test_params()
+        .filter(is_raceable)
+        .ok_or(JoinMatchmakingError::UnsupportedTestParams)?;
+    let protocol_version = params
+        .protocol_version()
+        .ok_or(JoinMatchmakingError::UnsupportedProtocolVersion)?;
+    let rating = rating::load_rating(
+        &mut *state.db().acquire().await?,
+        auth_token.user_id,
+        &test_params,
+    )
+    .await?
+    .rating;
+    Ok(ws.on_upgrade(move |socket| {
+        handle_socket(
+            state,
+            auth_token,
+            rating,
+            test_params,
+            protocol_version,
+            socket,
+        )
+    }))
 }
 
-async fn handle_socket(state: AppState, auth_token: AuthToken, socket: WebSocket) {
+async fn handle_socket(
+    state: AppState,
+    auth_token: AuthToken,
+    rating: f64,
+    test_params: RandomTestParams,
+    protocol_version: u32,
+    socket: WebSocket,
+) {
     let matchmaking = state.matchmaking();
-    let mut player = Player::new(auth_token.user_id, auth_token.username, socket);
+    let mut player = Player::new(auth_token.user_id, auth_token.username, rating, socket);
     if !player.ping().await {
         return;
     }
+    if player
+        .send(&ToPlayerMsg::Welcome { protocol_version })
+        .await
+        .is_err()
+    {
+        return;
+    }
     let (tx, rx) = oneshot::channel();
     matchmaking
         .send(MmsMsg::Join {
             player,
+            test_params,
             responder: tx,
         })
         .await
         .unwrap();
-    let _ = rx.await;
+    if let Ok(Err(MmsError::JoinError(mut player))) = rx.await {
+        let _ = player
+            .send(&ToPlayerMsg::Error {
+                title: "Already racing!",
+                body: "You are already in a race or looking for one",
+            })
+            .await;
+    }
+}
+
+/// Whether races can be held with the given test params. Tests must be known to exist, and must be
+/// short enough to be raced.
+fn is_raceable(test_params: &RandomTestParams) -> bool {
+    let is_short_enough = match test_params {
+        RandomTestParams::Words { length, .. } => (1..=MAX_RACE_LENGTH).contains(length),
+        RandomTestParams::Time { duration, .. } => (1..=MAX_RACE_DURATION).contains(duration),
+        RandomTestParams::Quote { .. } => true,
+    };
+    is_short_enough && test_params.seeded().is_some()
 }
 
 const PING_BYTES: [u8; 4] = [0, 1, 2, 3];
 
-const TIME_UNTIL_EVICTION: Duration = Duration::from_secs(5);
+/// The version of the protocol spoken over the websocket of a race. Clients say which version they
+/// speak when joining, and are told in the first message which version the server speaks with them.
+const PROTOCOL_VERSION: u32 = 1;
+const MIN_PROTOCOL_VERSION: u32 = 1;
+
 const MAX_USERS_PER_LOBBY: usize = 5;
+/// How long players are given to prepare once their race is found.
+const TIME_UNTIL_RACE_START: Duration = Duration::from_secs(5);
 const INACTIVITY_DURATION: Duration = Duration::from_secs(20);
+/// How long players who lose their connection mid-race have to reconnect before they're out of it.
+const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+const DEFAULT_RACE_LENGTH: u32 = 20;
+const MAX_RACE_LENGTH: u32 = 100;
+const MAX_RACE_DURATION: u32 = 120;
+
+/// How often the queue is checked for players that can be matched now that they've waited longer.
+const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+
+fn matchmaking_config() -> MatchmakingConfig {
+    MatchmakingConfig {
+        bucket_width: 50.0,
+        initial_tolerance: 100.0,
+        tolerance_growth_per_sec: 20.0,
+        max_tolerance: 600.0,
+        min_players: 2,
+        max_players: MAX_USERS_PER_LOBBY,
+        time_until_partial_match: Duration::from_secs(5),
+    }
+}
 
-pub fn spawn_matchmaking_service() -> Mms {
+pub fn spawn_matchmaking_service(db: Db, word_filter: WordFilter) -> Mms {
     let (tx, mut rx) = mpsc::channel::<MmsMsg>(32);
 
     let self_tx = tx.clone();
     tokio::spawn(async move {
         let mut lobby_id: u32 = 0;
-        let mut lobby = Vec::<Player>::new();
+        // Players only race others who queued for the same kind of test.
+        let mut queues = HashMap::<RandomTestParams, MatchmakingQueue<Player>>::new();
         let mut players = BTreeMap::<u32, u32>::new();
+        let mut races = HashMap::<u32, Sender<RaceEvent>>::new();
+        let mut interval = tokio::time::interval(MATCHMAKING_INTERVAL);
+
+        loop {
+            let mms_msg = tokio::select! {
+                mms_msg = rx.recv() => match mms_msg {
+                    Some(mms_msg) => Some(mms_msg),
+                    None => break,
+                },
+                _ = interval.tick() => None,
+            };
+
+            // Players who left while waiting for a race are let go of, so that they can queue again.
+            for queue in queues.values_mut() {
+                queue.retain(|player| !player.has_disconnected());
+            }
+            queues.retain(|_, queue| !queue.is_empty());
 
-        while let Some(mms_msg) = rx.recv().await {
             match mms_msg {
-                MmsMsg::Join {
-                    player: mut new_player,
+                Some(MmsMsg::Join {
+                    player: new_player,
+                    test_params,
                     responder,
-                } => {
-                    if players.get(&new_player.id).is_some() {
+                }) => {
+                    // Players still in a race are let back into it.
+                    if let Some(race_tx) = players
+                        .get(&new_player.id)
+                        .and_then(|lobby_id| races.get(lobby_id))
+                    {
+                        responder.send(Ok(())).unwrap();
+                        let race_tx = race_tx.clone();
+                        tokio::spawn(async move {
+                            let _ = race_tx.send(RaceEvent::Rejoin { player: new_player }).await;
+                        });
+                        continue;
+                    }
+                    if players.contains_key(&new_player.id)
+                        || queues.values().any(|queue| queue.contains(new_player.id))
+                    {
                         responder
                             .send(Err(MmsError::JoinError(new_player)))
                             .unwrap();
@@ -78,44 +215,66 @@ pub fn spawn_matchmaking_service() -> Mms {
                     }
 
                     responder.send(Ok(())).unwrap();
-                    players.insert(new_player.id.clone(), lobby_id);
-                    for player in &mut lobby {
-                        player
-                            .send(&joined_msg(&new_player.username))
-                            .await
-                            .unwrap();
-                        new_player
-                            .send(&joined_msg(&player.username))
-                            .await
-                            .unwrap();
+                    let (id, rating) = (new_player.id, new_player.rating);
+                    queues
+                        .entry(test_params)
+                        .or_insert_with(|| MatchmakingQueue::new(matchmaking_config()))
+                        .push(id, rating, new_player, Instant::now());
+                }
+                Some(MmsMsg::Leave { id, lobby_id }) => {
+                    if players.get(&id) == Some(&lobby_id) {
+                        players.remove(&id);
                     }
-                    lobby.push(new_player);
+                    continue;
+                }
+                Some(MmsMsg::RaceOver { lobby_id }) => {
+                    races.remove(&lobby_id);
+                    continue;
+                }
+                None => {}
+            }
 
-                    if lobby.len() == 2 {
-                        let mms = self_tx.clone();
-                        tokio::spawn(async move {
-                            sleep(TIME_UNTIL_EVICTION).await;
-                            mms.send(MmsMsg::Evict(lobby_id)).await.unwrap();
-                        });
-                    }
+            let now = Instant::now();
+            let lobbies: Vec<_> = queues
+                .iter_mut()
+                .flat_map(|(test_params, queue)| {
+                    queue
+                        .pop_matches(now)
+                        .into_iter()
+                        .map(|lobby| (test_params.clone(), lobby))
+                })
+                .collect();
+            queues.retain(|_, queue| !queue.is_empty());
 
-                    if lobby.len() == MAX_USERS_PER_LOBBY {
-                        let mms = self_tx.clone();
-                        tokio::spawn(start_race(mms, lobby_id, lobby.drain(..).collect()));
-                        lobby_id = lobby_id.wrapping_add(1);
-                    }
-                }
-                MmsMsg::Evict(id) => {
-                    if id == lobby_id {
-                        let mms = self_tx.clone();
-                        tokio::spawn(start_race(mms, lobby_id, lobby.drain(..).collect()));
-                    }
+            for (test_params, mut lobby) in lobbies {
+                lobby_id = lobby_id.wrapping_add(1);
+                for player in &lobby {
+                    players.insert(player.id, lobby_id);
                 }
-                MmsMsg::Leave { id, lobby_id } => {
-                    if players.get(&id) == Some(&lobby_id) {
-                        players.remove(&id);
+                for i in 0..lobby.len() {
+                    for j in 0..lobby.len() {
+                        if i != j {
+                            let username = lobby[j].username.clone();
+                            let _ = lobby[i]
+                                .send(&ToPlayerMsg::Joined {
+                                    username: &username,
+                                })
+                                .await;
+                        }
                     }
                 }
+                let (race_tx, race_rx) = mpsc::channel(32);
+                races.insert(lobby_id, race_tx.clone());
+                tokio::spawn(start_race(
+                    db.clone(),
+                    word_filter.clone(),
+                    self_tx.clone(),
+                    lobby_id,
+                    test_params,
+                    lobby,
+                    race_tx,
+                    race_rx,
+                ));
             }
         }
     });
@@ -131,13 +290,16 @@ pub type Mms = Sender<MmsMsg>;
 pub enum MmsMsg {
     Join {
         player: Player,
+        test_params: RandomTestParams,
         responder: Responder<()>,
     },
     Leave {
         id: u32,
         lobby_id: u32,
     },
-    Evict(u32),
+    RaceOver {
+        lobby_id: u32,
+    },
 }
 
 #[derive(Debug)]
@@ -145,192 +307,504 @@ pub enum MmsError {
     JoinError(Player),
 }
 
-async fn start_race(mms: Mms, lobby_id: u32, mut lobby: Vec<Player<WithRx>>) {
-    let seed: Seed = rand::random();
-    let start_msg = start_msg(seed);
-    for player in &mut lobby {
-        player.send(&start_msg).await.unwrap();
+#[allow(clippy::too_many_arguments)]
+async fn start_race(
+    db: Db,
+    word_filter: WordFilter,
+    mms: Mms,
+    lobby_id: u32,
+    test_params: RandomTestParams,
+    lobby: Vec<Player<WithRx>>,
+    race_tx: Sender<RaceEvent>,
+    mut race_rx: mpsc::Receiver<RaceEvent>,
+) {
+    let seeded_test_params = test_params
+        .seeded()
+        .expect("Test params checked on joining");
+    let start_msg = ToPlayerMsg::Start {
+        test_params: &seeded_test_params,
+    };
+    // Players who have lost their connection since being matched are left out of the race.
+    let mut absentees = Vec::new();
+    let mut started = Vec::new();
+    for mut player in lobby {
+        if player.send(&start_msg).await.is_ok() {
+            started.push(player);
+        } else {
+            leave_matchmaking(&mms, player.id, lobby_id).await;
+            absentees.push(player.username);
+        }
+    }
+    let lobby = started;
+    if lobby.is_empty() {
+        let _ = mms.send(MmsMsg::RaceOver { lobby_id }).await;
+        return;
     }
 
-    let (race_tx, mut race_rx) = mpsc::channel::<RaceMsg>(32);
+    let mut recorder = match RaceRecorder::new(
+        seeded_test_params.clone(),
+        true,
+        Instant::now() + TIME_UNTIL_RACE_START,
+        lobby.iter().map(|player| player.id),
+    ) {
+        Ok(recorder) => recorder,
+        Err(error) => {
+            tracing::error!("Failed to start race: {error:?}");
+            for mut player in lobby {
+                let _ = player
+                    .send(&ToPlayerMsg::Error {
+                        title: "Invalid test!",
+                        body: "Races cannot be held with this test",
+                    })
+                    .await;
+                leave_matchmaking(&mms, player.id, lobby_id).await;
+            }
+            let _ = mms.send(MmsMsg::RaceOver { lobby_id }).await;
+            return;
+        }
+    };
 
-    let mut race = Vec::new();
-    for player in lobby {
-        let race_tx = race_tx.clone();
-        let mms = mms.clone();
+    let mut chat = Chat::new(word_filter);
 
-        let (player_rx, player) = player.take_receiver();
-        let player_id = player.id;
+    let mut racers = BTreeMap::<u32, Racer>::new();
+    for player in lobby {
         let username = player.username.clone();
-        race.push(player);
-        tokio::spawn(async move {
-            let player_rx = tokio_stream::StreamExt::timeout(player_rx, INACTIVITY_DURATION);
-            tokio::pin!(player_rx);
-
-            while let Some(result) = player_rx.next().await {
-                let Ok(result) = result else {
-                    let _ = race_tx.send(RaceMsg::Timeout { username }).await;
-                    break;
-                };
-                let Ok(message) = result else {
-                    break;
-                };
-                match message {
-                    Message::Text(message) => {
-                        let race_msg = serde_json::from_str(&message).expect("no error");
-                        let is_finished = match &race_msg {
-                            RaceMsg::Finish { .. } => true,
-                            _ => false,
+        let (connection_id, player) = connect_racer(player, &race_tx);
+        racers.insert(
+            player.id,
+            Racer {
+                username,
+                connection_id,
+                player: Some(player),
+            },
+        );
+    }
+    for username in &absentees {
+        let msg = ToPlayerMsg::Disconnect {
+            username,
+            reason: DisconnectReason::Unknown,
+        };
+        send_msg_to_racers(&mut racers, None, &msg, &race_tx).await;
+    }
+
+    while !recorder.is_over() {
+        let Some(event) = race_rx.recv().await else {
+            break;
+        };
+
+        match event {
+            RaceEvent::Msg {
+                player_id,
+                connection_id,
+                msg,
+            } => {
+                let racer = &racers[&player_id];
+                if racer.connection_id != connection_id {
+                    continue;
+                }
+                let username = racer.username.clone();
+
+                // Only fair progress is passed on, and finish durations are measured by the server.
+                let msg = match msg {
+                    FromPlayerMsg::Update { progress } => {
+                        if !recorder.update(player_id, progress) {
+                            continue;
+                        }
+                        ToPlayerMsg::Update {
+                            username: &username,
+                            progress,
+                        }
+                    }
+                    FromPlayerMsg::Finish {} => {
+                        let finish = recorder.finish(player_id);
+                        if finish != Err(FinishRejection::NotRacing) {
+                            leave_matchmaking(&mms, player_id, lobby_id).await;
+                        }
+                        let duration = match finish {
+                            Ok(duration) => duration,
+                            Err(FinishRejection::NotRacing) => continue,
+                            // Suspicious finishes are kept from the other players.
+                            Err(FinishRejection::Suspicious) => {
+                                send_error_to_racer(
+                                    &mut racers,
+                                    player_id,
+                                    "Suspicious finish!",
+                                    "You couldn't have finished the race this quickly, so your finish doesn't count",
+                                )
+                                .await;
+                                continue;
+                            }
                         };
-                        race_tx.send(race_msg).await.unwrap();
-                        if is_finished {
-                            break;
+                        ToPlayerMsg::Finish {
+                            username: &username,
+                            result: duration.as_secs_f32(),
                         }
                     }
-                    Message::Close(_) => {
-                        break;
+                    FromPlayerMsg::Chat { text } => {
+                        match chat.post(player_id, &username, &text) {
+                            Ok(message) => {
+                                let msg = ToPlayerMsg::Chat { message };
+                                send_msg_to_racers(&mut racers, None, &msg, &race_tx).await;
+                            }
+                            Err(rejection) => {
+                                let (title, body) = rejection.describe();
+                                send_error_to_racer(&mut racers, player_id, title, body).await;
+                            }
+                        }
+                        continue;
                     }
-                    _ => {}
-                }
+                };
+                send_msg_to_racers(&mut racers, Some(player_id), &msg, &race_tx).await;
             }
-            let _ = mms
-                .send(MmsMsg::Leave {
-                    id: player_id,
-                    lobby_id,
-                })
+            RaceEvent::Malformed {
+                player_id,
+                connection_id,
+            } => {
+                if racers[&player_id].connection_id != connection_id {
+                    continue;
+                }
+                send_error_to_racer(
+                    &mut racers,
+                    player_id,
+                    "Invalid message!",
+                    "The server couldn't make sense of a message it was sent",
+                )
                 .await;
-        });
-    }
+            }
+            RaceEvent::Timeout {
+                player_id,
+                connection_id,
+            } => {
+                let racer = racers.get_mut(&player_id).expect("Player is present");
+                if racer.connection_id != connection_id {
+                    continue;
+                }
+                let username = racer.username.clone();
+                if let Some(mut player) = racer.player.take() {
+                    let _ = player
+                        .send(&ToPlayerMsg::Timeout {
+                            username: &username,
+                        })
+                        .await;
+                }
 
-    drop(race_tx);
+                recorder.disconnect(player_id, DisconnectReason::Timeout);
+                leave_matchmaking(&mms, player_id, lobby_id).await;
+                let msg = ToPlayerMsg::Disconnect {
+                    username: &username,
+                    reason: DisconnectReason::Timeout,
+                };
+                send_msg_to_racers(&mut racers, None, &msg, &race_tx).await;
+            }
+            RaceEvent::Closed {
+                player_id,
+                connection_id,
+            } => {
+                let racer = racers.get_mut(&player_id).expect("Player is present");
+                if racer.connection_id == connection_id {
+                    wait_for_reconnect(player_id, racer, &race_tx);
+                }
+            }
+            RaceEvent::ReconnectExpired {
+                player_id,
+                connection_id,
+            } => {
+                let racer = &racers[&player_id];
+                // Players who are done with the race aren't announced as having left it.
+                if racer.connection_id != connection_id
+                    || racer.player.is_some()
+                    || !recorder.is_racing(player_id)
+                {
+                    continue;
+                }
 
-    while let Some(message) = race_rx.recv().await {
-        match message {
-            RaceMsg::Update { ref username, .. } | RaceMsg::Finish { ref username, .. } => {
-                send_msg_to_players(
-                    &mms,
-                    lobby_id,
-                    &mut race,
-                    |player| player.username != *username,
-                    &message,
-                )
-                .await;
+                recorder.disconnect(player_id, DisconnectReason::Unknown);
+                leave_matchmaking(&mms, player_id, lobby_id).await;
+                let username = racer.username.clone();
+                let msg = ToPlayerMsg::Disconnect {
+                    username: &username,
+                    reason: DisconnectReason::Unknown,
+                };
+                send_msg_to_racers(&mut racers, None, &msg, &race_tx).await;
             }
-            RaceMsg::Timeout { ref username } => {
-                let pos = race
-                    .iter()
-                    .position(|player| player.username == *username)
-                    .expect("Player is present");
-                let _ = race[pos].send(&message).await;
-                race.swap_remove(pos);
-                send_msg_to_players(
-                    &mms,
-                    lobby_id,
-                    &mut race,
-                    |_| true,
-                    &RaceMsg::Disconnect {
-                        username: username.to_owned(),
-                        reason: DisconnectReason::Timeout,
-                    },
-                )
-                .await;
+            RaceEvent::Rejoin { player } => {
+                // Players who are done with the race have nothing to come back to.
+                if !recorder.is_racing(player.id) {
+                    continue;
+                }
+
+                let player_id = player.id;
+                let (connection_id, mut player) = connect_racer(player, &race_tx);
+                let is_resumed = player
+                    .send(&resume_msg(&recorder, &racers, &chat))
+                    .await
+                    .is_ok();
+                let racer = racers.get_mut(&player_id).expect("Player is present");
+                racer.connection_id = connection_id;
+                if is_resumed {
+                    racer.player = Some(player);
+                } else {
+                    wait_for_reconnect(player_id, racer, &race_tx);
+                }
             }
-            _ => {}
         }
     }
 
-    for player in race {
-        let _ = mms
-            .send(MmsMsg::Leave {
-                id: player.id,
-                lobby_id,
+    for &player_id in racers.keys() {
+        leave_matchmaking(&mms, player_id, lobby_id).await;
+    }
+    let _ = mms.send(MmsMsg::RaceOver { lobby_id }).await;
+
+    if let Err(error) = recorder.save(&db).await {
+        tracing::error!("Failed to save race: {error}");
+    }
+    if let Err(error) = update_ratings(&db, &test_params, &recorder.placements()).await {
+        tracing::error!("Failed to update ratings after race: {error}");
+    }
+}
+
+/// Listens to a new connection of a player in a race, returning the id of the connection so that
+/// events of connections that have since been replaced can be told apart.
+fn connect_racer(player: Player<WithRx>, race_tx: &Sender<RaceEvent>) -> (u32, Player<WithoutRx>) {
+    let connection_id = rand::random();
+    let (player_rx, player) = player.take_receiver();
+    let player_id = player.id;
+    let race_tx = race_tx.clone();
+
+    tokio::spawn(async move {
+        let player_rx = tokio_stream::StreamExt::timeout(player_rx, INACTIVITY_DURATION);
+        tokio::pin!(player_rx);
+
+        // Players who have finished can still chat, so their connection is listened to until it
+        // closes, but they're no longer expected to type.
+        let mut has_finished = false;
+        while let Some(result) = player_rx.next().await {
+            let Ok(result) = result else {
+                if has_finished {
+                    continue;
+                }
+                let _ = race_tx
+                    .send(RaceEvent::Timeout {
+                        player_id,
+                        connection_id,
+                    })
+                    .await;
+                return;
+            };
+            let Ok(message) = result else {
+                break;
+            };
+            match message {
+                Message::Text(message) => {
+                    let Ok(msg) = serde_json::from_str::<FromPlayerMsg>(&message) else {
+                        let _ = race_tx
+                            .send(RaceEvent::Malformed {
+                                player_id,
+                                connection_id,
+                            })
+                            .await;
+                        continue;
+                    };
+                    match msg {
+                        FromPlayerMsg::Update { .. } if has_finished => continue,
+                        FromPlayerMsg::Finish {} => has_finished = true,
+                        _ => {}
+                    }
+                    let _ = race_tx
+                        .send(RaceEvent::Msg {
+                            player_id,
+                            connection_id,
+                            msg,
+                        })
+                        .await;
+                }
+                Message::Close(_) => {
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let _ = race_tx
+            .send(RaceEvent::Closed {
+                player_id,
+                connection_id,
             })
             .await;
-    }
+    });
+
+    (connection_id, player)
 }
 
-// Method for sending messages to players. This takes care of handling send errors, marking them as
-// disconnected and informing the other remaining players of the same.
-async fn send_msg_to_players(
-    mms: &Mms,
-    lobby_id: u32,
-    race: &mut Vec<Player<WithoutRx>>,
-    filter: impl Fn(&Player<WithoutRx>) -> bool,
+/// Holds a player's place in the race for a while after they lose their connection, in case they
+/// come back.
+fn wait_for_reconnect(player_id: u32, racer: &mut Racer, race_tx: &Sender<RaceEvent>) {
+    racer.player = None;
+    let connection_id = racer.connection_id;
+    let race_tx = race_tx.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(RECONNECT_GRACE_PERIOD).await;
+        let _ = race_tx
+            .send(RaceEvent::ReconnectExpired {
+                player_id,
+                connection_id,
+            })
+            .await;
+    });
+}
+
+async fn leave_matchmaking(mms: &Mms, player_id: u32, lobby_id: u32) {
+    let _ = mms
+        .send(MmsMsg::Leave {
+            id: player_id,
+            lobby_id,
+        })
+        .await;
+}
+
+// Method for sending messages to the connected players of a race, except possibly one of them.
+// Players whose connection turns out to be lost are given time to reconnect.
+async fn send_msg_to_racers(
+    racers: &mut BTreeMap<u32, Racer>,
+    except: Option<u32>,
     message: &impl Serialize,
+    race_tx: &Sender<RaceEvent>,
 ) {
-    let mut disconnected_players = Vec::new();
-    for player in &mut *race {
-        if !filter(player) {
+    for (&player_id, racer) in racers.iter_mut() {
+        if Some(player_id) == except {
             continue;
         }
-        if player.send(&message).await.is_err() {
-            disconnected_players.push((player.id, player.username.clone()));
+        let Some(player) = &mut racer.player else {
+            continue;
+        };
+        if player.send(message).await.is_err() {
+            wait_for_reconnect(player_id, racer, race_tx);
         }
     }
+}
 
-    while !disconnected_players.is_empty() {
-        race.retain(|player| {
-            disconnected_players
-                .iter()
-                .find(|disconnected_player| player.id == disconnected_player.0)
-                .is_none()
-        });
-
-        let mut new_disconnected_players = Vec::new();
-        for player in &mut *race {
-            let mut is_disconnected = false;
-            for disconnected_player in &disconnected_players {
-                let message = RaceMsg::Disconnect {
-                    username: disconnected_player.1.clone(),
-                    reason: DisconnectReason::Unknown,
-                };
-                if !is_disconnected && player.send(&message).await.is_err() {
-                    new_disconnected_players.push((player.id, player.username.clone()));
-                    is_disconnected = true;
-                }
-            }
-        }
-
-        for player in disconnected_players {
-            let _ = mms
-                .send(MmsMsg::Leave {
-                    id: player.0,
-                    lobby_id,
-                })
-                .await;
-        }
-
-        disconnected_players = new_disconnected_players;
+async fn send_error_to_racer(
+    racers: &mut BTreeMap<u32, Racer>,
+    player_id: u32,
+    title: &str,
+    body: &str,
+) {
+    let racer = racers.get_mut(&player_id).expect("Player is present");
+    if let Some(player) = &mut racer.player {
+        let _ = player.send(&ToPlayerMsg::Error { title, body }).await;
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(rename_all_fields = "camelCase")]
 #[serde(tag = "kind", content = "payload")]
-enum RaceMsg {
+enum FromPlayerMsg {
+    Update { progress: u32 },
+    Finish {},
+    Chat { text: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(rename_all_fields = "camelCase")]
+#[serde(tag = "kind", content = "payload")]
+enum ToPlayerMsg<'a> {
+    /// The first message sent to players, with the version of the protocol the server speaks.
+    Welcome {
+        protocol_version: u32,
+    },
+    Joined {
+        username: &'a str,
+    },
+    Start {
+        test_params: &'a SeededTestParams,
+    },
+    /// Sent to players who reconnect to a race, with everything they need to pick up where they
+    /// left.
+    Resume {
+        test_params: &'a SeededTestParams,
+        time_until_race_start: Duration,
+        elapsed: Duration,
+        players: Vec<RacerProgress<'a>>,
+        chat: Vec<&'a ChatMessage>,
+    },
     Update {
-        username: String,
-        progress: usize,
+        username: &'a str,
+        progress: u32,
     },
     Finish {
-        username: String,
+        username: &'a str,
         result: f32,
     },
     Disconnect {
-        username: String,
+        username: &'a str,
         reason: DisconnectReason,
     },
     Timeout {
-        username: String,
+        username: &'a str,
     },
+    Chat {
+        message: &'a ChatMessage,
+    },
+    Error {
+        title: &'a str,
+        body: &'a str,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RacerProgress<'a> {
+    username: &'a str,
+    progress: u32,
+    result: Option<f32>,
+}
+
+/// Everything that a race task is told of, by the players' connections and by the matchmaking
+/// service.
+#[derive(Debug)]
+enum RaceEvent {
+    Msg {
+        player_id: u32,
+        connection_id: u32,
+        msg: FromPlayerMsg,
+    },
+    /// A player sent a message that isn't part of the protocol.
+    Malformed {
+        player_id: u32,
+        connection_id: u32,
+    },
+    Timeout {
+        player_id: u32,
+        connection_id: u32,
+    },
+    Closed {
+        player_id: u32,
+        connection_id: u32,
+    },
+    ReconnectExpired {
+        player_id: u32,
+        connection_id: u32,
+    },
+    Rejoin {
+        player: Player,
+    },
+}
+
+struct Racer {
+    username: String,
+    connection_id: u32,
+    /// Absent while the player is disconnected.
+    player: Option<Player<WithoutRx>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-enum DisconnectReason {
+pub enum DisconnectReason {
     Unknown,
     Timeout,
+    /// Only happens in rooms, where the host can kick players.
+    Kicked,
 }
 
 type PlayerTx = SplitSink<WebSocket, Message>;
@@ -342,16 +816,18 @@ type WithoutRx = ();
 pub struct Player<Rx = WithRx> {
     pub id: u32,
     pub username: String,
+    pub rating: f64,
     pub sender: PlayerTx,
     receiver: Rx,
 }
 
 impl Player {
-    fn new(id: u32, username: String, socket: WebSocket) -> Self {
+    fn new(id: u32, username: String, rating: f64, socket: WebSocket) -> Self {
         let (sender, receiver) = socket.split();
         Self {
             id,
             username,
+            rating,
             sender,
             receiver,
         }
@@ -388,12 +864,25 @@ impl Player<WithRx> {
         return message == Message::Pong(PING_BYTES.to_vec());
     }
 
+    /// Whether the player's connection is known to be closed, without waiting on it. Players aren't
+    /// expected to say anything until their race starts, so anything they did say is dropped.
+    pub fn has_disconnected(&mut self) -> bool {
+        loop {
+            match self.receiver.next().now_or_never() {
+                None => return false,
+                Some(Some(Ok(Message::Close(_))) | Some(Err(_)) | None) => return true,
+                Some(Some(Ok(_))) => {}
+            }
+        }
+    }
+
     pub fn take_receiver(self) -> (PlayerRx, Player<WithoutRx>) {
         (
             self.receiver,
             Player::<WithoutRx> {
                 id: self.id,
                 username: self.username,
+                rating: self.rating,
                 sender: self.sender,
                 receiver: (),
             },
@@ -401,24 +890,106 @@ impl Player<WithRx> {
     }
 }
 
-fn joined_msg(new_player_name: &String) -> impl Serialize {
-    use serde_json::json;
-    json!({
-        "kind": "joined",
-        "payload": {
-            "username": new_player_name,
-        },
-    })
+/// Sent to players who reconnect to a race.
+fn resume_msg<'a>(
+    recorder: &'a RaceRecorder,
+    racers: &'a BTreeMap<u32, Racer>,
+    chat: &'a Chat,
+) -> ToPlayerMsg<'a> {
+    let now = Instant::now();
+    let players = racers
+        .iter()
+        .filter_map(|(&player_id, racer)| {
+            let (progress, finish_duration) = recorder.progress(player_id)?;
+            Some(RacerProgress {
+                username: &racer.username,
+                progress,
+                result: finish_duration.map(|duration| duration.as_secs_f32()),
+            })
+        })
+        .collect();
+    ToPlayerMsg::Resume {
+        test_params: recorder.test_params(),
+        time_until_race_start: recorder.start().saturating_duration_since(now),
+        elapsed: now.saturating_duration_since(recorder.start()),
+        players,
+        chat: chat.history().collect(),
+    }
+}
+
+impl JoinMatchmakingParams {
+    /// The newest version of the protocol spoken by both the server and the player, who are
+    /// assumed to speak the first version if they don't say.
+    fn protocol_version(&self) -> Option<u32> {
+        let version = self
+            .protocol_version
+            .unwrap_or(MIN_PROTOCOL_VERSION)
+            .min(PROTOCOL_VERSION);
+        (version >= MIN_PROTOCOL_VERSION).then_some(version)
+    }
+
+    /// Players who don't ask for any test in particular race the frontend's default test.
+    fn test_params(&self) -> Option<RandomTestParams> {
+        let language = || {
+            self.language
+                .to_owned()
+                .unwrap_or_else(|| "english".to_owned())
+        };
+        match self.mode {
+            Some(TypingTestMode::Words) | None => Some(RandomTestParams::Words {
+                language: language(),
+                length: self.length.unwrap_or(DEFAULT_RACE_LENGTH),
+            }),
+            Some(TypingTestMode::Time) => Some(RandomTestParams::Time {
+                language: language(),
+                duration: self.duration?,
+            }),
+            Some(TypingTestMode::Quote) => Some(RandomTestParams::Quote {
+                length: self.quote_length?,
+            }),
+        }
+    }
+}
+
+impl From<sqlx::Error> for JoinMatchmakingError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for JoinMatchmakingError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::UnsupportedTestParams => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Races cannot be held with the given test params",
+            ),
+            Self::UnsupportedProtocolVersion => (
+                StatusCode::BAD_REQUEST,
+                "The server doesn't speak any version of the protocol the client speaks",
+            ),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinMatchmakingParams {
+    mode: Option<TypingTestMode>,
+    language: Option<String>,
+    length: Option<u32>,
+    duration: Option<u32>,
+    quote_length: Option<QuoteModeLength>,
+    protocol_version: Option<u32>,
 }
 
-fn start_msg(seed: Seed) -> impl Serialize {
-    use serde_json::json;
-    json!({
-        "kind": "start",
-        "payload": {
-            "seed": seed,
-        },
-    })
+#[derive(Debug, Serialize)]
+pub enum JoinMatchmakingError {
+    UnsupportedTestParams,
+    UnsupportedProtocolVersion,
+    Other,
 }
 
 impl<State> Debug for Player<State> {
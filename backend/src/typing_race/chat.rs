@@ -0,0 +1,242 @@
+//! Chat between players in rooms and in races found through matchmaking. Messages are limited in
+//! length and rate, and words in the filter are masked before anyone sees them.
+//!
+//! The filter is usually configured through the environment, with `CHAT_FILTERED_WORDS` being a
+//! comma separated list of words to mask regardless of case.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use regex::{Captures, Regex};
+use serde::Serialize;
+
+const MAX_MESSAGE_LENGTH: usize = 200;
+/// Players can send at most this many messages within the window below.
+const MAX_MESSAGES_PER_WINDOW: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+/// How many of the latest messages are kept to be shown to those who join later.
+const HISTORY_LENGTH: usize = 50;
+
+/// Words to mask in chat messages, regardless of case. Cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct WordFilter(Option<Regex>);
+
+#[derive(Debug)]
+pub struct Chat {
+    word_filter: WordFilter,
+    history: VecDeque<ChatMessage>,
+    recent_messages: HashMap<u32, VecDeque<Instant>>,
+    muted_ids: HashSet<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub username: String,
+    pub text: String,
+    #[serde(with = "ts_milliseconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatRejection {
+    Empty,
+    TooLong,
+    TooFast,
+    Muted,
+}
+
+impl ChatRejection {
+    /// A title and body to tell the player why their message wasn't sent.
+    pub fn describe(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Empty => ("Empty message!", "There's nothing to send"),
+            Self::TooLong => (
+                "Message too long!",
+                "Messages can be at most 200 characters long",
+            ),
+            Self::TooFast => (
+                "Slow down!",
+                "You're sending messages too quickly, wait a few seconds",
+            ),
+            Self::Muted => ("You're muted!", "The host has muted you in this room"),
+        }
+    }
+}
+
+impl WordFilter {
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>) -> Self {
+        let words: Vec<String> = words
+            .into_iter()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(regex::escape)
+            .collect();
+        if words.is_empty() {
+            return Self(None);
+        }
+        let pattern = format!(r"(?i)\b({})\b", words.join("|"));
+        Self(Some(
+            Regex::new(&pattern).expect("Escaped words make a valid pattern"),
+        ))
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("CHAT_FILTERED_WORDS")
+                .unwrap_or_default()
+                .split(','),
+        )
+    }
+
+    fn mask(&self, text: &str) -> String {
+        match &self.0 {
+            Some(regex) => regex
+                .replace_all(text, |captures: &Captures| {
+                    "*".repeat(captures[0].chars().count())
+                })
+                .into_owned(),
+            None => text.to_owned(),
+        }
+    }
+}
+
+impl Chat {
+    pub fn new(word_filter: WordFilter) -> Self {
+        Self {
+            word_filter,
+            history: VecDeque::new(),
+            recent_messages: HashMap::new(),
+            muted_ids: HashSet::new(),
+        }
+    }
+
+    /// Posts a message from a player, returning it with any filtered words masked.
+    pub fn post(
+        &mut self,
+        player_id: u32,
+        username: &str,
+        text: &str,
+    ) -> Result<&ChatMessage, ChatRejection> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(ChatRejection::Empty);
+        }
+        if text.chars().count() > MAX_MESSAGE_LENGTH {
+            return Err(ChatRejection::TooLong);
+        }
+        if self.muted_ids.contains(&player_id) {
+            return Err(ChatRejection::Muted);
+        }
+
+        let now = Instant::now();
+        let recent_messages = self.recent_messages.entry(player_id).or_default();
+        while recent_messages
+            .front()
+            .is_some_and(|&sent_at| now.duration_since(sent_at) >= RATE_LIMIT_WINDOW)
+        {
+            recent_messages.pop_front();
+        }
+        if recent_messages.len() >= MAX_MESSAGES_PER_WINDOW {
+            return Err(ChatRejection::TooFast);
+        }
+        recent_messages.push_back(now);
+
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(ChatMessage {
+            username: username.to_owned(),
+            text: self.word_filter.mask(text),
+            timestamp: Utc::now(),
+        });
+        Ok(self.history.back().expect("message was just pushed"))
+    }
+
+    pub fn set_muted(&mut self, player_id: u32, is_muted: bool) {
+        if is_muted {
+            self.muted_ids.insert(player_id);
+        } else {
+            self.muted_ids.remove(&player_id);
+        }
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.history.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(chat: &mut Chat, player_id: u32, text: &str) -> Result<String, ChatRejection> {
+        chat.post(player_id, "player", text)
+            .map(|message| message.text.clone())
+    }
+
+    #[test]
+    fn limits_the_rate_of_messages() {
+        let mut chat = Chat::new(WordFilter::default());
+        for _ in 0..MAX_MESSAGES_PER_WINDOW {
+            assert!(post(&mut chat, 1, "hi").is_ok());
+        }
+        assert_eq!(post(&mut chat, 1, "hi"), Err(ChatRejection::TooFast));
+        // Other players have their own limit.
+        assert!(post(&mut chat, 2, "hi").is_ok());
+
+        // Messages only count towards the limit for as long as the window.
+        for sent_at in chat.recent_messages.get_mut(&1).unwrap() {
+            *sent_at -= RATE_LIMIT_WINDOW;
+        }
+        assert!(post(&mut chat, 1, "hi").is_ok());
+    }
+
+    #[test]
+    fn limits_the_length_of_messages() {
+        let mut chat = Chat::new(WordFilter::default());
+        let longest = "é".repeat(MAX_MESSAGE_LENGTH);
+        assert_eq!(post(&mut chat, 1, &longest), Ok(longest.clone()));
+        assert_eq!(
+            post(&mut chat, 1, &format!("{longest}e")),
+            Err(ChatRejection::TooLong),
+        );
+        // Surrounding whitespace is trimmed before the length is checked.
+        assert_eq!(post(&mut chat, 1, &format!("  {longest}  ")), Ok(longest));
+        assert_eq!(post(&mut chat, 1, "   "), Err(ChatRejection::Empty));
+    }
+
+    #[test]
+    fn keeps_muted_players_from_chatting() {
+        let mut chat = Chat::new(WordFilter::default());
+        chat.set_muted(1, true);
+        assert_eq!(post(&mut chat, 1, "hi"), Err(ChatRejection::Muted));
+        assert!(post(&mut chat, 2, "hi").is_ok());
+        chat.set_muted(1, false);
+        assert!(post(&mut chat, 1, "hi").is_ok());
+        assert_eq!(chat.history().count(), 2);
+    }
+
+    #[test]
+    fn masks_filtered_words() {
+        let mut chat = Chat::new(WordFilter::new(" darn, heck ,,".split(',')));
+        assert_eq!(
+            post(&mut chat, 1, "Darn it, what the HECK"),
+            Ok("**** it, what the ****".to_owned()),
+        );
+        // Only whole words are masked.
+        assert_eq!(
+            post(&mut chat, 1, "darned heckler"),
+            Ok("darned heckler".to_owned()),
+        );
+    }
+
+    #[test]
+    fn escapes_filtered_words() {
+        let mut chat = Chat::new(WordFilter::new(["a.c"]));
+        assert_eq!(post(&mut chat, 1, "abc a.c"), Ok("abc ***".to_owned()));
+    }
+}
@@ -0,0 +1,545 @@
+//! Recording of races, both in matchmaking and in rooms, so that players can look back on them.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{mysql::MySqlRow, Row};
+
+use crate::{
+    auth::AuthToken,
+    common::state::Db,
+    typing_test::{gen::GenerateError, SeededTestParams},
+};
+
+use super::{referee::Referee, DisconnectReason};
+
+/// A race that is being recorded as it happens. Only progress and finishes that the referee deems
+/// fair are recorded, and players who finish suspiciously are flagged.
+#[derive(Debug)]
+pub struct RaceRecorder {
+    test_params: SeededTestParams,
+    is_rated: bool,
+    started_at: DateTime<Utc>,
+    referee: Referee,
+    participants: Vec<Participant>,
+    n_finished: usize,
+}
+
+#[derive(Debug)]
+struct Participant {
+    user_id: u32,
+    progress: u32,
+    /// How many players finished before this one, along with how long it took them.
+    finish: Option<(usize, Duration)>,
+    disconnect_reason: Option<DisconnectReason>,
+    is_flagged: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishRejection {
+    NotRacing,
+    /// The player couldn't have finished by now, and has been flagged.
+    Suspicious,
+}
+
+impl RaceRecorder {
+    /// Starts recording a race that starts at the given instant. Fails if the test doesn't exist.
+    pub fn new(
+        test_params: SeededTestParams,
+        is_rated: bool,
+        start: Instant,
+        user_ids: impl IntoIterator<Item = u32>,
+    ) -> Result<Self, GenerateError> {
+        let started_at = Utc::now()
+            + start
+                .checked_duration_since(Instant::now())
+                .unwrap_or_default();
+        Ok(Self {
+            referee: Referee::new(&test_params, start)?,
+            test_params,
+            is_rated,
+            started_at,
+            participants: user_ids
+                .into_iter()
+                .map(|user_id| Participant {
+                    user_id,
+                    progress: 0,
+                    finish: None,
+                    disconnect_reason: None,
+                    is_flagged: false,
+                })
+                .collect(),
+            n_finished: 0,
+        })
+    }
+
+    /// Records the progress of a player, returning whether it was fair.
+    pub fn update(&mut self, user_id: u32, progress: u32) -> bool {
+        let now = Instant::now();
+        let Some(participant) = self
+            .participants
+            .iter_mut()
+            .find(|participant| participant.user_id == user_id && participant.is_racing())
+        else {
+            return false;
+        };
+        if !self
+            .referee
+            .is_fair_update(participant.progress, progress, now)
+        {
+            return false;
+        }
+        participant.progress = progress;
+        true
+    }
+
+    /// Records a player finishing now, returning how long they took.
+    pub fn finish(&mut self, user_id: u32) -> Result<Duration, FinishRejection> {
+        let now = Instant::now();
+        let n_finished = self.n_finished;
+        let Some(participant) = self
+            .participants
+            .iter_mut()
+            .find(|participant| participant.user_id == user_id && participant.is_racing())
+        else {
+            return Err(FinishRejection::NotRacing);
+        };
+        let Some(duration) = self.referee.finish_duration(participant.progress, now) else {
+            participant.is_flagged = true;
+            return Err(FinishRejection::Suspicious);
+        };
+        participant.finish = Some((n_finished, duration));
+        self.n_finished += 1;
+        Ok(duration)
+    }
+
+    pub fn disconnect(&mut self, user_id: u32, reason: DisconnectReason) {
+        if let Some(participant) = self
+            .participants
+            .iter_mut()
+            .find(|participant| participant.user_id == user_id && participant.is_racing())
+        {
+            participant.disconnect_reason = Some(reason);
+        }
+    }
+
+    pub fn test_params(&self) -> &SeededTestParams {
+        &self.test_params
+    }
+
+    /// The instant at which the race starts, as kept by the referee.
+    pub fn start(&self) -> Instant {
+        self.referee.start()
+    }
+
+    /// Whether a player is still in the race, having neither finished, disconnected nor been
+    /// flagged.
+    pub fn is_racing(&self, user_id: u32) -> bool {
+        self.participant(user_id)
+            .is_some_and(|participant| participant.is_racing())
+    }
+
+    /// The last fair progress of a player, along with how long they took if they finished.
+    pub fn progress(&self, user_id: u32) -> Option<(u32, Option<Duration>)> {
+        self.participant(user_id).map(|participant| {
+            (
+                participant.progress,
+                participant.finish.map(|(_, duration)| duration),
+            )
+        })
+    }
+
+    fn participant(&self, user_id: u32) -> Option<&Participant> {
+        self.participants
+            .iter()
+            .find(|participant| participant.user_id == user_id)
+    }
+
+    /// Whether every participant has either finished, disconnected or been flagged.
+    pub fn is_over(&self) -> bool {
+        self.participants
+            .iter()
+            .all(|participant| !participant.is_racing())
+    }
+
+    /// Places the participants, with a lower placement being better. Participants who finished
+    /// are placed by the order in which they finished, or by how far they got in time mode, where
+    /// everyone finishes together. Participants who didn't finish or were flagged are placed last.
+    pub fn placements(&self) -> Vec<(u32, u32)> {
+        // Lower keys are better, and participants without a key didn't finish.
+        let keys: Vec<(u32, Option<i64>)> = self
+            .participants
+            .iter()
+            .map(|participant| {
+                let key = participant
+                    .finish
+                    .map(|(n_finished_before, _)| match self.test_params {
+                        SeededTestParams::Time { .. } => -i64::from(participant.progress),
+                        _ => n_finished_before as i64,
+                    });
+                (participant.user_id, key)
+            })
+            .collect();
+
+        let is_better = |a: Option<i64>, b: Option<i64>| match (a, b) {
+            (Some(a), Some(b)) => a < b,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        keys.iter()
+            .map(|&(user_id, key)| {
+                let n_better = keys
+                    .iter()
+                    .filter(|&&(_, other_key)| is_better(other_key, key))
+                    .count();
+                (user_id, n_better as u32 + 1)
+            })
+            .collect()
+    }
+
+    /// Saves the race, treating participants who are still racing as having disconnected.
+    pub async fn save(&self, db: &Db) -> Result<u32, sqlx::Error> {
+        let mut tx = db.begin().await?;
+
+        let race_id = sqlx::query(
+            "INSERT INTO race (test_params, is_rated, started_timestamp) VALUES (?, ?, ?)",
+        )
+        .bind(serde_json::to_string(&self.test_params).expect("no error"))
+        .bind(self.is_rated)
+        .bind(self.started_at)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id() as u32;
+
+        for (participant, (_, placement)) in self.participants.iter().zip(self.placements()) {
+            let finish_duration = participant
+                .finish
+                .map(|(_, duration)| duration.as_millis() as u32);
+            let disconnect_reason = match participant.disconnect_reason {
+                _ if participant.finish.is_some() || participant.is_flagged => None,
+                Some(DisconnectReason::Timeout) => Some("timeout"),
+                Some(DisconnectReason::Kicked) => Some("kicked"),
+                _ => Some("unknown"),
+            };
+            sqlx::query(
+                "INSERT INTO race_participant (race_id, user_id, placement, finish_duration, disconnect_reason, is_flagged) VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(race_id)
+            .bind(participant.user_id)
+            .bind(placement)
+            .bind(finish_duration)
+            .bind(disconnect_reason)
+            .bind(participant.is_flagged)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(race_id)
+    }
+}
+
+impl Participant {
+    fn is_racing(&self) -> bool {
+        self.finish.is_none() && self.disconnect_reason.is_none() && !self.is_flagged
+    }
+}
+
+/// Saves a race in the background, since nobody waits on it.
+pub fn spawn_save_race(db: Db, race: RaceRecorder) {
+    tokio::spawn(async move {
+        if let Err(error) = race.save(&db).await {
+            tracing::error!("Failed to save race: {error}");
+        }
+    });
+}
+
+pub async fn get_races(
+    db: Db,
+    auth_token: AuthToken,
+    Query(params): Query<GetRacesParams>,
+) -> Result<Json<GetRacesResponse>, GetRacesError> {
+    if params.limit == 0 {
+        return Err(GetRacesError::NonPositiveLimit);
+    }
+
+    let mut races: Vec<RaceSummary> = sqlx::query(
+        "SELECT race.id, test_params, is_rated, started_timestamp, finished_timestamp, placement, finish_duration, disconnect_reason, is_flagged,
+            (SELECT COUNT(*) FROM race_participant AS other WHERE other.race_id = race.id) AS n_participants
+        FROM race_participant JOIN race ON race.id = race_participant.race_id
+        WHERE user_id = ? AND race.id < ?
+        ORDER BY race.id DESC LIMIT ?",
+    )
+    .bind(auth_token.user_id)
+    .bind(params.cursor.unwrap_or(u32::MAX))
+    .bind(params.limit + 1)
+    .fetch_all(&db)
+    .await?
+    .into_iter()
+    .map(RaceSummary::from)
+    .collect();
+
+    // Races are paged by their id, newest first.
+    let races_exhausted = races.len() < (params.limit + 1) as usize;
+    if !races_exhausted {
+        races.pop();
+    }
+    let cursor = if races_exhausted {
+        0
+    } else {
+        races.last().unwrap().id
+    };
+
+    Ok(Json(GetRacesResponse { cursor, races }))
+}
+
+/// Races are public, so that they can be shared with anyone, signed in or not.
+pub async fn get_race(db: Db, Path(race_id): Path<u32>) -> Result<Json<Race>, GetRaceError> {
+    let race = sqlx::query(
+        "SELECT test_params, is_rated, started_timestamp, finished_timestamp FROM race WHERE id = ?",
+    )
+    .bind(race_id)
+    .fetch_optional(&db)
+    .await?
+    .ok_or(GetRaceError::RaceNotFound)?;
+
+    let participants = sqlx::query(
+        "SELECT username, placement, finish_duration, disconnect_reason, is_flagged
+        FROM race_participant JOIN user ON user.id = race_participant.user_id
+        WHERE race_id = ?
+        ORDER BY placement",
+    )
+    .bind(race_id)
+    .fetch_all(&db)
+    .await?
+    .into_iter()
+    .map(|row| RaceParticipant {
+        username: row.get("username"),
+        placement: row.get("placement"),
+        finish_duration: row.get("finish_duration"),
+        disconnect_reason: row.get("disconnect_reason"),
+        is_flagged: row.get("is_flagged"),
+    })
+    .collect();
+
+    let test_params: String = race.get("test_params");
+    Ok(Json(Race {
+        id: race_id,
+        test_params: serde_json::from_str(&test_params).expect("Well-formed serialization"),
+        is_rated: race.get("is_rated"),
+        started_timestamp: race.get("started_timestamp"),
+        finished_timestamp: race.get("finished_timestamp"),
+        participants,
+    }))
+}
+
+impl From<MySqlRow> for RaceSummary {
+    fn from(row: MySqlRow) -> Self {
+        let test_params: String = row.get("test_params");
+        Self {
+            id: row.get("id"),
+            test_params: serde_json::from_str(&test_params).expect("Well-formed serialization"),
+            is_rated: row.get("is_rated"),
+            started_timestamp: row.get("started_timestamp"),
+            finished_timestamp: row.get("finished_timestamp"),
+            n_participants: row.get::<i64, _>("n_participants") as u32,
+            placement: row.get("placement"),
+            finish_duration: row.get("finish_duration"),
+            disconnect_reason: row.get("disconnect_reason"),
+            is_flagged: row.get("is_flagged"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for GetRacesError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for GetRacesError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::NonPositiveLimit => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Limit should be a strictly positive integer",
+            ),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+impl From<sqlx::Error> for GetRaceError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for GetRaceError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::RaceNotFound => (StatusCode::NOT_FOUND, "Race not found"),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetRacesParams {
+    cursor: Option<u32>,
+    limit: u32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetRacesResponse {
+    /// The cursor to fetch the next page with, or 0 if there are no more races.
+    cursor: u32,
+    races: Vec<RaceSummary>,
+}
+
+/// A race from the point of view of one of its participants.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceSummary {
+    id: u32,
+    test_params: SeededTestParams,
+    is_rated: bool,
+    #[serde(with = "ts_milliseconds")]
+    started_timestamp: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    finished_timestamp: DateTime<Utc>,
+    n_participants: u32,
+    placement: u32,
+    /// In milliseconds, if the participant finished.
+    finish_duration: Option<u32>,
+    disconnect_reason: Option<String>,
+    /// Whether the participant finished suspiciously, in which case they're placed last.
+    is_flagged: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub enum GetRacesError {
+    NonPositiveLimit,
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Race {
+    id: u32,
+    test_params: SeededTestParams,
+    is_rated: bool,
+    #[serde(with = "ts_milliseconds")]
+    started_timestamp: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    finished_timestamp: DateTime<Utc>,
+    participants: Vec<RaceParticipant>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RaceParticipant {
+    username: String,
+    placement: u32,
+    /// In milliseconds, if the participant finished.
+    finish_duration: Option<u32>,
+    disconnect_reason: Option<String>,
+    /// Whether the participant finished suspiciously, in which case they're placed last.
+    is_flagged: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub enum GetRaceError {
+    RaceNotFound,
+    Other,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder(test_params: SeededTestParams, user_ids: [u32; 4]) -> RaceRecorder {
+        RaceRecorder::new(test_params, false, Instant::now(), user_ids).unwrap()
+    }
+
+    fn words_mode() -> SeededTestParams {
+        SeededTestParams::Words {
+            language: "english".to_owned(),
+            length: 10,
+            seed: [1, 2, 3, 4],
+        }
+    }
+
+    fn time_mode() -> SeededTestParams {
+        SeededTestParams::Time {
+            language: "english".to_owned(),
+            duration: 15,
+            seed: [1, 2, 3, 4],
+        }
+    }
+
+    // Sets the outcome of a participant directly, bypassing the referee.
+    fn set(race: &mut RaceRecorder, user_id: u32, progress: u32, finished: bool) {
+        let n_finished = race.n_finished;
+        let participant = race
+            .participants
+            .iter_mut()
+            .find(|participant| participant.user_id == user_id)
+            .unwrap();
+        participant.progress = progress;
+        if finished {
+            participant.finish = Some((n_finished, Duration::from_secs(15)));
+            race.n_finished += 1;
+        }
+    }
+
+    #[test]
+    fn places_by_finishing_order() {
+        let mut race = recorder(words_mode(), [1, 2, 3, 4]);
+        set(&mut race, 3, 9, true);
+        set(&mut race, 1, 9, true);
+        set(&mut race, 4, 9, true);
+        set(&mut race, 2, 9, true);
+        assert_eq!(race.placements(), [(1, 2), (2, 4), (3, 1), (4, 3)]);
+    }
+
+    #[test]
+    fn places_by_progress_in_time_mode() {
+        let mut race = recorder(time_mode(), [1, 2, 3, 4]);
+        // Everyone finishes at the same time, in whatever order their finishes arrive.
+        set(&mut race, 1, 20, true);
+        set(&mut race, 2, 35, true);
+        set(&mut race, 3, 20, true);
+        set(&mut race, 4, 41, true);
+        assert_eq!(race.placements(), [(1, 3), (2, 2), (3, 3), (4, 1)]);
+    }
+
+    #[test]
+    fn places_players_who_did_not_finish_last_together() {
+        let mut race = recorder(words_mode(), [1, 2, 3, 4]);
+        set(&mut race, 2, 9, true);
+        race.disconnect(1, DisconnectReason::Timeout);
+        // Progress doesn't count for players who didn't finish.
+        set(&mut race, 3, 8, false);
+        set(&mut race, 4, 2, false);
+        race.participants[3].is_flagged = true;
+        assert_eq!(race.placements(), [(1, 2), (2, 1), (3, 2), (4, 2)]);
+
+        let mut race = recorder(time_mode(), [1, 2, 3, 4]);
+        set(&mut race, 1, 30, false);
+        set(&mut race, 2, 10, true);
+        set(&mut race, 3, 20, true);
+        assert_eq!(race.placements(), [(1, 3), (2, 2), (3, 1), (4, 3)]);
+    }
+}
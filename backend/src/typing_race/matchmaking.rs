@@ -0,0 +1,316 @@
+//! The matchmaking algorithm, which groups waiting players of similar rating into races. It is kept
+//! apart from the matchmaking service and knows nothing of websockets, so that it can be driven on
+//! its own with any kind of player and any notion of time.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct MatchmakingConfig {
+    /// Players are kept in buckets of ratings of this width, so that only the buckets within a
+    /// player's tolerance are searched for opponents.
+    pub bucket_width: f64,
+    /// How far off in rating a player's opponents may be as soon as they join.
+    pub initial_tolerance: f64,
+    /// How much the tolerance widens for every second that a player waits.
+    pub tolerance_growth_per_sec: f64,
+    pub max_tolerance: f64,
+    pub min_players: usize,
+    pub max_players: usize,
+    /// How long to wait for a race to fill up before starting it with fewer players.
+    pub time_until_partial_match: Duration,
+}
+
+pub struct MatchmakingQueue<T> {
+    config: MatchmakingConfig,
+    buckets: BTreeMap<i64, Vec<QueuedPlayer<T>>>,
+    bucket_of_player: HashMap<u32, i64>,
+}
+
+struct QueuedPlayer<T> {
+    id: u32,
+    rating: f64,
+    joined_at: Instant,
+    player: T,
+}
+
+impl<T> MatchmakingQueue<T> {
+    pub fn new(config: MatchmakingConfig) -> Self {
+        Self {
+            config,
+            buckets: BTreeMap::new(),
+            bucket_of_player: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.bucket_of_player.contains_key(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bucket_of_player.is_empty()
+    }
+
+    pub fn push(&mut self, id: u32, rating: f64, player: T, now: Instant) {
+        if self.contains(id) {
+            return;
+        }
+        let bucket = self.bucket(rating);
+        self.bucket_of_player.insert(id, bucket);
+        self.buckets.entry(bucket).or_default().push(QueuedPlayer {
+            id,
+            rating,
+            joined_at: now,
+            player,
+        });
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<T> {
+        let bucket = self.bucket_of_player.remove(&id)?;
+        let players = self.buckets.get_mut(&bucket)?;
+        let pos = players.iter().position(|queued| queued.id == id)?;
+        let queued = players.swap_remove(pos);
+        if players.is_empty() {
+            self.buckets.remove(&bucket);
+        }
+        Some(queued.player)
+    }
+
+    /// Keeps only the players for whom `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&mut T) -> bool) {
+        let bucket_of_player = &mut self.bucket_of_player;
+        self.buckets.retain(|_, players| {
+            players.retain_mut(|queued| {
+                let is_kept = keep(&mut queued.player);
+                if !is_kept {
+                    bucket_of_player.remove(&queued.id);
+                }
+                is_kept
+            });
+            !players.is_empty()
+        });
+    }
+
+    /// Takes out every group of players that should race each other now. Players who have waited
+    /// the longest are matched first, each with the closest rated players that they are mutually
+    /// within tolerance of.
+    pub fn pop_matches(&mut self, now: Instant) -> Vec<Vec<T>> {
+        let mut anchors: Vec<(Instant, u32)> = self
+            .buckets
+            .values()
+            .flatten()
+            .map(|queued| (queued.joined_at, queued.id))
+            .collect();
+        anchors.sort();
+
+        let mut matches = Vec::new();
+        for (_, anchor_id) in anchors {
+            let Some(group) = self.find_group(anchor_id, now) else {
+                continue;
+            };
+            matches.push(group.into_iter().filter_map(|id| self.remove(id)).collect());
+        }
+        matches
+    }
+
+    fn find_group(&self, anchor_id: u32, now: Instant) -> Option<Vec<u32>> {
+        let anchor = self.get(anchor_id)?;
+        let anchor_tolerance = self.tolerance(anchor, now);
+
+        let lowest_bucket = self.bucket(anchor.rating - anchor_tolerance);
+        let highest_bucket = self.bucket(anchor.rating + anchor_tolerance);
+        let mut candidates: Vec<(f64, Instant, u32)> = self
+            .buckets
+            .range(lowest_bucket..=highest_bucket)
+            .flat_map(|(_, players)| players)
+            .filter(|queued| queued.id != anchor_id)
+            .filter_map(|queued| {
+                let difference = (queued.rating - anchor.rating).abs();
+                let tolerance = anchor_tolerance.min(self.tolerance(queued, now));
+                (difference <= tolerance).then_some((difference, queued.joined_at, queued.id))
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        candidates.truncate(self.config.max_players - 1);
+
+        let group_size = candidates.len() + 1;
+        let is_full = group_size >= self.config.max_players;
+        let has_waited_enough =
+            now.duration_since(anchor.joined_at) >= self.config.time_until_partial_match;
+        let is_partial_match = group_size >= self.config.min_players && has_waited_enough;
+        if !is_full && !is_partial_match {
+            return None;
+        }
+
+        let mut group = vec![anchor_id];
+        group.extend(candidates.into_iter().map(|(_, _, id)| id));
+        Some(group)
+    }
+
+    fn get(&self, id: u32) -> Option<&QueuedPlayer<T>> {
+        let bucket = self.bucket_of_player.get(&id)?;
+        self.buckets
+            .get(bucket)?
+            .iter()
+            .find(|queued| queued.id == id)
+    }
+
+    fn tolerance(&self, queued: &QueuedPlayer<T>, now: Instant) -> f64 {
+        let waited = now.duration_since(queued.joined_at).as_secs_f64();
+        (self.config.initial_tolerance + self.config.tolerance_growth_per_sec * waited)
+            .min(self.config.max_tolerance)
+    }
+
+    fn bucket(&self, rating: f64) -> i64 {
+        (rating / self.config.bucket_width).floor() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue() -> MatchmakingQueue<u32> {
+        MatchmakingQueue::new(MatchmakingConfig {
+            bucket_width: 50.0,
+            initial_tolerance: 100.0,
+            tolerance_growth_per_sec: 20.0,
+            max_tolerance: 600.0,
+            min_players: 2,
+            max_players: 3,
+            time_until_partial_match: Duration::from_secs(5),
+        })
+    }
+
+    fn push(queue: &mut MatchmakingQueue<u32>, id: u32, rating: f64, joined_at: Instant) {
+        queue.push(id, rating, id, joined_at);
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn matches_full_races_at_once() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1050.0, now);
+        push(&mut queue, 3, 940.0, now);
+
+        assert_eq!(queue.pop_matches(now), [[1, 2, 3]]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn matches_partial_races_once_players_have_waited() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1050.0, now + secs(1));
+
+        assert!(queue.pop_matches(now + secs(4)).is_empty());
+        assert_eq!(queue.pop_matches(now + secs(5)), [[1, 2]]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn never_matches_lone_players() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+
+        assert!(queue.pop_matches(now + secs(60)).is_empty());
+        assert!(queue.contains(1));
+    }
+
+    #[test]
+    fn widens_the_tolerance_over_time() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1300.0, now);
+
+        // The tolerance is 100 + 20 per second waited.
+        assert!(queue.pop_matches(now + secs(9)).is_empty());
+        assert_eq!(queue.pop_matches(now + secs(10)), [[1, 2]]);
+    }
+
+    #[test]
+    fn requires_players_to_be_within_each_others_tolerance() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1200.0, now + secs(10));
+
+        // The first player would race the second long before the second would race the first.
+        assert!(queue.pop_matches(now + secs(14)).is_empty());
+        assert_eq!(queue.pop_matches(now + secs(15)), [[1, 2]]);
+    }
+
+    #[test]
+    fn caps_the_tolerance() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1601.0, now);
+
+        assert!(queue.pop_matches(now + secs(3600)).is_empty());
+    }
+
+    #[test]
+    fn matches_the_longest_waiting_players_with_the_closest_rated_ones() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1090.0, now + secs(1));
+        push(&mut queue, 3, 1010.0, now + secs(2));
+        push(&mut queue, 4, 1020.0, now + secs(3));
+
+        // Only the furthest rated player is left out of the race, which is full.
+        assert_eq!(queue.pop_matches(now + secs(3)), [[1, 3, 4]]);
+        assert!(queue.contains(2));
+        assert_eq!(queue.pop_matches(now + secs(6)), Vec::<Vec<u32>>::new());
+    }
+
+    #[test]
+    fn splits_many_players_into_races_of_at_most_max_players() {
+        let (mut queue, now) = (queue(), Instant::now());
+        for id in 0..7 {
+            push(
+                &mut queue,
+                id,
+                1000.0,
+                now + Duration::from_millis(id as u64),
+            );
+        }
+
+        let matches = queue.pop_matches(now + secs(5));
+        assert_eq!(matches, [vec![0, 1, 2], vec![3, 4, 5]]);
+        assert!(queue.contains(6));
+    }
+
+    #[test]
+    fn removes_players() {
+        let (mut queue, now) = (queue(), Instant::now());
+        push(&mut queue, 1, 1000.0, now);
+        push(&mut queue, 2, 1000.0, now);
+
+        assert_eq!(queue.remove(1), Some(1));
+        assert_eq!(queue.remove(1), None);
+        assert!(!queue.contains(1));
+        assert!(queue.pop_matches(now + secs(60)).is_empty());
+
+        assert_eq!(queue.remove(2), Some(2));
+        assert!(queue.is_empty());
+        assert!(queue.buckets.is_empty());
+    }
+
+    #[test]
+    fn retains_players() {
+        let (mut queue, now) = (queue(), Instant::now());
+        for id in 0..4 {
+            push(&mut queue, id, 1000.0 + 100.0 * id as f64, now);
+        }
+
+        queue.retain(|id| *id % 2 == 0);
+        assert!(queue.contains(0) && queue.contains(2));
+        assert!(!queue.contains(1) && !queue.contains(3));
+        assert_eq!(queue.buckets.len(), 2);
+    }
+}
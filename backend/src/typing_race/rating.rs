@@ -0,0 +1,340 @@
+//! Ratings of players in races, computed with the Glicko-2 rating system and kept separately for
+//! every typing test mode. See http://www.glicko.net/glicko/glicko2.pdf for the details of the
+//! system, whose steps are followed closely here.
+//!
+//! Every race is its own rating period, in which each player has played a game against every other
+//! player in the race, winning against those placed below them and drawing with those placed the
+//! same.
+
+use std::f64::consts::PI;
+
+use axum::Json;
+use serde::Serialize;
+use sqlx::{MySqlConnection, Row};
+
+use crate::{
+    auth::AuthToken,
+    common::{error::AppError, state::Db},
+    typing_test::RandomTestParams,
+};
+
+/// The ratio between ratings on the Glicko scale and on the Glicko-2 scale.
+const SCALE: f64 = 173.7178;
+/// Constrains how much the volatility can change in a single rating period.
+const TAU: f64 = 0.5;
+/// The tolerance to which the new volatility is computed.
+const EPSILON: f64 = 0.000001;
+
+/// The rating of players who are yet to race in a mode.
+pub const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+
+/// Players who haven't raced in a mode yet start out with a rating derived from the average WPM of
+/// their tests in that mode, so that they are matched sensibly from the start.
+const DEFAULT_WPM: f64 = 40.0;
+const RATING_PER_WPM: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Glicko2Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko2Rating {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+impl Glicko2Rating {
+    /// The rating of a player who is yet to race, given the average WPM of their tests, if any.
+    pub fn provisional(average_wpm: Option<f64>) -> Self {
+        Self {
+            rating: average_wpm.map_or(DEFAULT_RATING, |average_wpm| {
+                DEFAULT_RATING + (average_wpm - DEFAULT_WPM) * RATING_PER_WPM
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Computes the rating after a rating period with the given games, each being the opponent's
+    /// rating and the score against them (1 for a win, 0.5 for a draw and 0 for a loss).
+    pub fn update(&self, games: &[(Glicko2Rating, f64)]) -> Self {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        let sigma = self.volatility;
+
+        if games.is_empty() {
+            return Self {
+                deviation: (phi * phi + sigma * sigma).sqrt() * SCALE,
+                ..*self
+            };
+        }
+
+        let games: Vec<(f64, f64, f64)> = games
+            .iter()
+            .map(|(opponent, score)| {
+                let mu_j = (opponent.rating - DEFAULT_RATING) / SCALE;
+                let g = g(opponent.deviation / SCALE);
+                let e = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+                (g, e, *score)
+            })
+            .collect();
+
+        let v = 1.0
+            / games
+                .iter()
+                .map(|(g, e, _)| g * g * e * (1.0 - e))
+                .sum::<f64>();
+        let improvement: f64 = games.iter().map(|(g, e, score)| g * (score - e)).sum();
+        let delta = v * improvement;
+
+        let sigma = new_volatility(phi, sigma, v, delta);
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * improvement;
+
+        Self {
+            rating: mu * SCALE + DEFAULT_RATING,
+            deviation: phi * SCALE,
+            volatility: sigma,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+/// Finds the new volatility with the Illinois algorithm, as in step 5 of the paper.
+fn new_volatility(phi: f64, sigma: f64, v: f64, delta: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        let d = phi * phi + v + ex;
+        ex * (delta * delta - d) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+    };
+
+    let mut lower = a;
+    let mut upper = if delta * delta > phi * phi + v {
+        (delta * delta - phi * phi - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * TAU
+    };
+
+    let mut f_lower = f(lower);
+    let mut f_upper = f(upper);
+    while (upper - lower).abs() > EPSILON {
+        let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+        let f_c = f(c);
+        if f_c * f_upper <= 0.0 {
+            lower = upper;
+            f_lower = f_upper;
+        } else {
+            f_lower /= 2.0;
+        }
+        upper = c;
+        f_upper = f_c;
+    }
+
+    (lower / 2.0).exp()
+}
+
+/// Loads the rating of a user in the given mode.
+pub async fn load_rating(
+    conn: &mut MySqlConnection,
+    user_id: u32,
+    test_params: &RandomTestParams,
+) -> Result<Glicko2Rating, sqlx::Error> {
+    let test_params = serde_json::to_string(test_params).expect("no error");
+
+    let rating = sqlx::query(
+        "SELECT rating, deviation, volatility FROM rating WHERE user_id = ? AND test_params = ?",
+    )
+    .bind(user_id)
+    .bind(&test_params)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(rating) = rating {
+        return Ok(Glicko2Rating {
+            rating: rating.get("rating"),
+            deviation: rating.get("deviation"),
+            volatility: rating.get("volatility"),
+        });
+    }
+
+    let average_wpm: Option<f64> = sqlx::query_scalar(
+        "SELECT sum_wpm / n_results FROM stat WHERE user_id = ? AND test_params = ?",
+    )
+    .bind(user_id)
+    .bind(&test_params)
+    .fetch_optional(&mut *conn)
+    .await?
+    .flatten();
+    Ok(Glicko2Rating::provisional(average_wpm))
+}
+
+/// Updates the ratings of the players of a race, given as user ids with their placements, where a
+/// lower placement is better and equal placements are draws.
+pub async fn update_ratings(
+    db: &Db,
+    test_params: &RandomTestParams,
+    placements: &[(u32, u32)],
+) -> Result<(), sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let mut ratings = Vec::with_capacity(placements.len());
+    for &(user_id, _) in placements {
+        ratings.push(load_rating(&mut tx, user_id, test_params).await?);
+    }
+
+    let test_params = serde_json::to_string(test_params).expect("no error");
+    for (i, &(user_id, placement)) in placements.iter().enumerate() {
+        let games: Vec<(Glicko2Rating, f64)> = placements
+            .iter()
+            .zip(&ratings)
+            .enumerate()
+            .filter(|(j, _)| i != *j)
+            .map(|(_, (&(_, opponent_placement), opponent))| {
+                let score = match placement.cmp(&opponent_placement) {
+                    std::cmp::Ordering::Less => 1.0,
+                    std::cmp::Ordering::Equal => 0.5,
+                    std::cmp::Ordering::Greater => 0.0,
+                };
+                (*opponent, score)
+            })
+            .collect();
+        let rating = ratings[i].update(&games);
+
+        sqlx::query(
+            "INSERT INTO rating (user_id, test_params, rating, deviation, volatility, n_races) VALUES (?, ?, ?, ?, ?, 1)
+            ON DUPLICATE KEY UPDATE rating = VALUES(rating), deviation = VALUES(deviation), volatility = VALUES(volatility), n_races = n_races + 1",
+        )
+        .bind(user_id)
+        .bind(&test_params)
+        .bind(rating.rating)
+        .bind(rating.deviation)
+        .bind(rating.volatility)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+pub async fn get_ratings(
+    db: Db,
+    auth_token: AuthToken,
+) -> Result<Json<GetRatingsResponse>, AppError> {
+    let ratings = sqlx::query(
+        "SELECT test_params, rating, deviation, volatility, n_races FROM rating WHERE user_id = ?",
+    )
+    .bind(auth_token.user_id)
+    .fetch_all(&db)
+    .await?
+    .into_iter()
+    .map(|row| {
+        let test_params: String = row.get("test_params");
+        Rating {
+            test_params: test_params.into(),
+            rating: Glicko2Rating {
+                rating: row.get("rating"),
+                deviation: row.get("deviation"),
+                volatility: row.get("volatility"),
+            },
+            n_races: row.get("n_races"),
+        }
+    })
+    .collect();
+
+    Ok(Json(GetRatingsResponse { ratings }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRatingsResponse {
+    ratings: Vec<Rating>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rating {
+    test_params: RandomTestParams,
+    #[serde(flatten)]
+    rating: Glicko2Rating,
+    n_races: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    fn rating(rating: f64, deviation: f64) -> Glicko2Rating {
+        Glicko2Rating {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    // The example worked through in the paper, with a player rated 1500 who beats a player rated
+    // 1400 and loses to players rated 1550 and 1700.
+
+    #[test]
+    fn updates_ratings_as_in_the_paper() {
+        let games = [
+            (rating(1400.0, 30.0), 1.0),
+            (rating(1550.0, 100.0), 0.0),
+            (rating(1700.0, 300.0), 0.0),
+        ];
+        let updated = rating(1500.0, 200.0).update(&games);
+
+        assert_close(updated.rating, 1464.06, 0.01);
+        assert_close(updated.deviation, 151.52, 0.01);
+        assert_close(updated.volatility, 0.05999, 0.00001);
+    }
+
+    #[test]
+    fn iterates_to_the_volatility_in_the_paper() {
+        let phi = 200.0 / SCALE;
+        assert_close(new_volatility(phi, 0.06, 1.7785, -0.4834), 0.05999, 0.00001);
+    }
+
+    #[test]
+    fn widens_the_deviation_without_games() {
+        let updated = rating(1500.0, 200.0).update(&[]);
+
+        assert_eq!(updated.rating, 1500.0);
+        assert_close(updated.deviation, 200.27, 0.01);
+        assert_eq!(updated.volatility, DEFAULT_VOLATILITY);
+    }
+
+    #[test]
+    fn derives_provisional_ratings_from_the_average_wpm() {
+        assert_eq!(Glicko2Rating::provisional(None), Glicko2Rating::default());
+        assert_eq!(Glicko2Rating::provisional(Some(40.0)).rating, 1500.0);
+        assert_eq!(Glicko2Rating::provisional(Some(100.0)).rating, 2100.0);
+        assert_eq!(Glicko2Rating::provisional(Some(25.0)).rating, 1350.0);
+
+        let provisional = Glicko2Rating::provisional(Some(100.0));
+        assert_eq!(provisional.deviation, DEFAULT_DEVIATION);
+        assert_eq!(provisional.volatility, DEFAULT_VOLATILITY);
+    }
+}
@@ -0,0 +1,180 @@
+//! Refereeing of races. The server keeps its own clock for every race, so that finish durations
+//! don't depend on what players claim, and checks the progress of players against what anyone
+//! could possibly type.
+
+use std::time::{Duration, Instant};
+
+use crate::typing_test::{
+    gen::{generate, GenerateError, MAX_KEYSTROKES_PER_SEC},
+    SeededTestParams,
+};
+
+/// Leeway for the latency between the server's and the players' clocks.
+const LATENCY_ALLOWANCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct Referee {
+    start: Instant,
+    goal: Goal,
+    /// The number of characters, spaces included, typed by someone who has typed each number of
+    /// words of the test.
+    typed_chars: Vec<u32>,
+}
+
+#[derive(Debug)]
+enum Goal {
+    /// The number of words to type, in words and quote mode.
+    Words(u32),
+    /// How long to type for, in time mode.
+    Time(Duration),
+}
+
+impl Referee {
+    /// Fails if the test doesn't exist.
+    pub fn new(test_params: &SeededTestParams, start: Instant) -> Result<Self, GenerateError> {
+        let test = generate(test_params)?;
+        let goal = match test_params {
+            SeededTestParams::Time { duration, .. } => {
+                Goal::Time(Duration::from_secs((*duration).into()))
+            }
+            // Quotes are counted in the words that the test is split into.
+            _ => Goal::Words(test.len() as u32),
+        };
+        let typed_chars = std::iter::once(0)
+            .chain(test.iter().scan(0, |typed_chars, word| {
+                *typed_chars += word.chars().count() as u32 + 1;
+                Some(*typed_chars)
+            }))
+            .collect();
+        Ok(Self {
+            start,
+            goal,
+            typed_chars,
+        })
+    }
+
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    /// Whether a player could have gone from one progress to the other by now. Progress never goes
+    /// back, and can't be made before the race starts.
+    pub fn is_fair_update(&self, previous: u32, progress: u32, now: Instant) -> bool {
+        let Some(elapsed) = now.checked_duration_since(self.start) else {
+            return false;
+        };
+        // Time mode tests have more words than anyone could type in time.
+        let Some(&typed_chars) = self.typed_chars.get(progress as usize) else {
+            return false;
+        };
+        progress >= previous && typed_chars <= max_typed_chars(elapsed)
+    }
+
+    /// Checks that a player could have finished the race by now, given their last progress, and
+    /// returns how long they took if so.
+    pub fn finish_duration(&self, progress: u32, now: Instant) -> Option<Duration> {
+        let elapsed = now.checked_duration_since(self.start)?;
+        let is_fair = match self.goal {
+            // The last word finishes the test rather than being sent as progress, and isn't
+            // followed by a space.
+            Goal::Words(n_words) => {
+                progress + 1 >= n_words
+                    && self.typed_chars[n_words as usize] - 1 <= max_typed_chars(elapsed)
+            }
+            Goal::Time(duration) => elapsed + LATENCY_ALLOWANCE >= duration,
+        };
+        is_fair.then_some(elapsed)
+    }
+}
+
+/// Every character takes at least one keystroke.
+fn max_typed_chars(elapsed: Duration) -> u32 {
+    ((elapsed + LATENCY_ALLOWANCE).as_secs_f64() * f64::from(MAX_KEYSTROKES_PER_SEC)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The test is "have who that way never hold begin it general much", which takes 50 characters,
+    // and 24 to type its first five words and the spaces after them. At 35 keystrokes per second,
+    // that's at least 1429ms and 686ms, less the allowance for latency.
+
+    fn words_mode(start: Instant) -> Referee {
+        let test_params = SeededTestParams::Words {
+            language: "english".to_owned(),
+            length: 10,
+            seed: [1, 2, 3, 4],
+        };
+        Referee::new(&test_params, start).unwrap()
+    }
+
+    fn after(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn accepts_fair_updates() {
+        let start = Instant::now();
+        let referee = words_mode(start);
+        assert!(referee.is_fair_update(0, 1, after(start, 0)));
+        assert!(referee.is_fair_update(1, 5, after(start, 200)));
+        assert!(referee.is_fair_update(5, 5, after(start, 200)));
+        assert!(referee.is_fair_update(5, 10, after(start, 2000)));
+    }
+
+    #[test]
+    fn rejects_updates_that_are_too_fast_or_go_back() {
+        let start = Instant::now();
+        let referee = words_mode(start);
+        assert!(!referee.is_fair_update(1, 5, after(start, 150)));
+        assert!(!referee.is_fair_update(5, 4, after(start, 2000)));
+        assert!(!referee.is_fair_update(0, 11, after(start, 60_000)));
+
+        let referee = words_mode(after(start, 1000));
+        assert!(!referee.is_fair_update(0, 1, start));
+    }
+
+    #[test]
+    fn rejects_finishes_that_are_too_fast() {
+        let start = Instant::now();
+        let referee = words_mode(start);
+        assert_eq!(referee.finish_duration(9, after(start, 900)), None);
+        assert_eq!(
+            referee.finish_duration(9, after(start, 950)),
+            Some(Duration::from_millis(950)),
+        );
+        // The last word finishes the test, but the ones before it must have been sent as progress.
+        assert_eq!(referee.finish_duration(8, after(start, 60_000)), None);
+    }
+
+    #[test]
+    fn only_accepts_time_mode_finishes_once_time_is_up() {
+        let start = Instant::now();
+        let test_params = SeededTestParams::Time {
+            language: "english".to_owned(),
+            duration: 15,
+            seed: [1, 2, 3, 4],
+        };
+        let referee = Referee::new(&test_params, start).unwrap();
+        assert!(referee.is_fair_update(0, 30, after(start, 10_000)));
+        assert_eq!(referee.finish_duration(30, after(start, 14_000)), None);
+        assert_eq!(
+            referee.finish_duration(30, after(start, 14_600)),
+            Some(Duration::from_millis(14_600)),
+        );
+    }
+
+    #[test]
+    fn fails_on_unknown_tests() {
+        let test_params = SeededTestParams::Words {
+            language: "klingon".to_owned(),
+            length: 10,
+            seed: [1, 2, 3, 4],
+        };
+        assert_eq!(
+            Referee::new(&test_params, Instant::now()).unwrap_err(),
+            GenerateError::UnknownLanguage,
+        );
+    }
+}
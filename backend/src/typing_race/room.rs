@@ -1,113 +1,441 @@
-use std::{collections::HashMap, iter::zip, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    iter::zip,
+    time::{Duration, Instant},
+};
 
 use axum::{
     extract::{
         ws::{Message, WebSocket},
         Query, State, WebSocketUpgrade,
     },
-    response::IntoResponse,
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
     Json,
 };
 use futures::{
     future::{join, join_all},
-    SinkExt, StreamExt,
+    stream, SinkExt, Stream, StreamExt,
 };
 use rand::seq::IteratorRandom;
 use serde::{Deserialize, Serialize};
 use tokio::{
     sync::{
+        broadcast,
         mpsc::{self, Sender},
         oneshot,
     },
+    task::JoinHandle,
     time::sleep,
 };
 
 use crate::{
     auth::AuthToken,
-    common::{error::AppError, state::AppState},
-    typing_test::Seed,
+    common::{
+        error::AppError,
+        state::{AppState, Db},
+    },
+    ghosts::{load_ghost, Ghost},
+    typing_test::{replay::ProgressPoint, RandomTestParams, SeededTestParams},
 };
 
-use super::{Player, PlayerRx, PlayerTx};
+use super::{
+    chat::{Chat, ChatMessage, WordFilter},
+    history::{spawn_save_race, FinishRejection, RaceRecorder},
+    is_raceable,
+    rating::DEFAULT_RATING,
+    DisconnectReason, Player, PlayerRx, PlayerTx,
+};
 
 pub async fn create_room(
     State(state): State<AppState>,
     auth_token: AuthToken,
-) -> Result<Json<CreateRoomResponse>, AppError> {
-    let (tx, rx) = oneshot::channel();
+    Json(params): Json<CreateRoomParams>,
+) -> Result<Json<CreateRoomResponse>, CreateRoomError> {
+    let max_players = params.max_players.unwrap_or(MAX_PLAYERS_PER_ROOM);
+    if !(1..=MAX_PLAYERS_PER_ROOM).contains(&max_players) {
+        return Err(CreateRoomError::InvalidMaxPlayers);
+    }
+    let countdown = params.countdown.unwrap_or(DEFAULT_COUNTDOWN);
+    if !(MIN_COUNTDOWN..=MAX_COUNTDOWN).contains(&countdown) {
+        return Err(CreateRoomError::InvalidCountdown);
+    }
+    let n_rounds = params.n_rounds.unwrap_or(1);
+    if !(1..=MAX_ROUNDS).contains(&n_rounds) {
+        return Err(CreateRoomError::InvalidNumberOfRounds);
+    }
+    let test_params = params.test_params.unwrap_or_else(default_test_params);
+    if !is_raceable(&test_params) {
+        return Err(CreateRoomError::UnsupportedTestParams);
+    }
+    let settings = RoomSettings {
+        max_players,
+        is_private: params.is_private,
+        password: params.password.filter(|password| !password.is_empty()),
+        countdown: Duration::from_secs(countdown),
+        n_rounds,
+    };
 
+    let (tx, rx) = oneshot::channel();
     state
         .room_mgr()
         .send(RoomMgmtMsg::CreateRoom {
             creator_id: auth_token.user_id,
+            settings,
+            test_params,
             responder: tx,
         })
-        .await?;
+        .await
+        .map_err(|_| CreateRoomError::Other)?;
 
-    let room_id = rx.await?;
+    let room_id = rx.await.map_err(|_| CreateRoomError::Other)?;
     Ok(Json(CreateRoomResponse { room_id }))
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRoomParams {
+    max_players: Option<usize>,
+    /// Private rooms can only be joined by those who are given their id.
+    #[serde(default)]
+    is_private: bool,
+    password: Option<String>,
+    test_params: Option<RandomTestParams>,
+    /// How many seconds players are given to prepare before every race.
+    countdown: Option<u64>,
+    /// How many rounds there are in every session, over which points add up.
+    n_rounds: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub enum CreateRoomError {
+    InvalidMaxPlayers,
+    InvalidCountdown,
+    InvalidNumberOfRounds,
+    UnsupportedTestParams,
+    Other,
+}
+
+impl IntoResponse for CreateRoomError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::InvalidMaxPlayers => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Rooms must allow at least 1 and at most 10 players",
+            ),
+            Self::InvalidCountdown => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The countdown must be between 3 and 60 seconds",
+            ),
+            Self::InvalidNumberOfRounds => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "There must be between 1 and 20 rounds",
+            ),
+            Self::UnsupportedTestParams => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Races cannot be held with this test",
+            ),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateRoomResponse {
     room_id: RoomId,
 }
 
+pub async fn get_rooms(State(state): State<AppState>) -> Result<Json<GetRoomsResponse>, AppError> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .room_mgr()
+        .send(RoomMgmtMsg::ListRooms { responder: tx })
+        .await?;
+
+    let rooms = rx.await?;
+    Ok(Json(GetRoomsResponse { rooms }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRoomsResponse {
+    rooms: Vec<RoomListing>,
+}
+
+/// Streams the rooms listed in the room browser as server-sent events, starting with all of them
+/// and followed by every change to them.
+pub async fn watch_rooms(
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let (tx, rx) = oneshot::channel();
+    state
+        .room_mgr()
+        .send(RoomMgmtMsg::WatchRooms { responder: tx })
+        .await?;
+
+    let (rooms, receiver) = rx.await?;
+    let changes = stream::unfold(receiver, |mut receiver| async move {
+        // Watchers who fall behind are cut off, and can catch up by watching again.
+        let event = receiver.recv().await.ok()?;
+        Some((event, receiver))
+    });
+    let events = stream::once(async { RoomListEvent::Init { rooms } })
+        .chain(changes)
+        .map(|event| Event::default().json_data(event));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 pub async fn join_room(
     State(state): State<AppState>,
     auth_token: AuthToken,
     ws: WebSocketUpgrade,
-    Query(JoinRoomParams { room_id }): Query<JoinRoomParams>,
+    Query(JoinRoomParams { room_id, password }): Query<JoinRoomParams>,
 ) -> impl IntoResponse {
     async fn handle_socket(
         state: AppState,
         auth_token: AuthToken,
         socket: WebSocket,
         room_id: RoomId,
+        password: Option<String>,
     ) {
         let room_mgr = state.room_mgr();
-        let player = Player::new(auth_token.user_id, auth_token.username, socket);
+        // Players in rooms aren't matched, so their rating doesn't matter.
+        let player = Player::new(
+            auth_token.user_id,
+            auth_token.username,
+            DEFAULT_RATING,
+            socket,
+        );
         room_mgr
-            .send(RoomMgmtMsg::JoinRoom { room_id, player })
+            .send(RoomMgmtMsg::JoinRoom {
+                room_id,
+                player,
+                password,
+            })
             .await
             .unwrap();
     }
 
-    ws.on_upgrade(move |socket| handle_socket(state, auth_token, socket, room_id))
+    ws.on_upgrade(move |socket| handle_socket(state, auth_token, socket, room_id, password))
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinRoomParams {
     room_id: RoomId,
+    password: Option<String>,
+}
+
+pub async fn spectate_room(
+    State(state): State<AppState>,
+    ws: WebSocketUpgrade,
+    Query(JoinRoomParams { room_id, password }): Query<JoinRoomParams>,
+) -> impl IntoResponse {
+    async fn handle_socket(
+        state: AppState,
+        socket: WebSocket,
+        room_id: RoomId,
+        password: Option<String>,
+    ) {
+        let (sender, receiver) = socket.split();
+        let _ = state
+            .room_mgr()
+            .send(RoomMgmtMsg::SpectateRoom {
+                room_id,
+                spectator: Spectator { sender, receiver },
+                password,
+            })
+            .await;
+    }
+
+    ws.on_upgrade(move |socket| handle_socket(state, socket, room_id, password))
+}
+
+pub async fn add_ghost_to_room(
+    State(state): State<AppState>,
+    db: Db,
+    auth_token: AuthToken,
+    Json(AddGhostParams { room_id, ghost_id }): Json<AddGhostParams>,
+) -> Result<Json<()>, AddGhostError> {
+    let ghost = load_ghost(&db, &ghost_id)
+        .await?
+        .ok_or(AddGhostError::GhostNotFound)?;
+
+    let (tx, rx) = oneshot::channel();
+    state
+        .room_mgr()
+        .send(RoomMgmtMsg::AddGhost {
+            room_id,
+            player_id: auth_token.user_id,
+            ghost,
+            responder: tx,
+        })
+        .await
+        .map_err(|_| AddGhostError::Other)?;
+
+    rx.await.map_err(|_| AddGhostError::Other)??;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddGhostParams {
+    room_id: RoomId,
+    ghost_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub enum AddGhostError {
+    RoomNotFound,
+    GhostNotFound,
+    NotHost,
+    TooLate,
+    Other,
 }
 
-pub fn spawn_room_manager() -> RoomMgr {
+impl From<sqlx::Error> for AddGhostError {
+    fn from(_error: sqlx::Error) -> Self {
+        Self::Other
+    }
+}
+
+impl IntoResponse for AddGhostError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::RoomNotFound => (StatusCode::NOT_FOUND, "Room not found"),
+            Self::GhostNotFound => (StatusCode::NOT_FOUND, "Ghost not found or expired"),
+            Self::NotHost => (
+                StatusCode::FORBIDDEN,
+                "Only the host can add ghosts to the room",
+            ),
+            Self::TooLate => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The racing phase has begun, ghosts cannot be added now",
+            ),
+            Self::Other => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
+        }
+        .into_response()
+    }
+}
+
+pub fn spawn_room_manager(db: Db, word_filter: WordFilter) -> RoomMgr {
     let (tx, mut rx) = mpsc::channel::<RoomMgmtMsg>(32);
 
     let self_tx = tx.clone();
     tokio::spawn(async move {
         let mut rooms = HashMap::<RoomId, Room>::new();
+        // Public rooms that can be joined, which are listed in the room browser.
+        let mut listings = HashMap::<RoomId, RoomListing>::new();
+        let (listings_tx, _) = broadcast::channel::<RoomListEvent>(64);
 
         while let Some(room_mgmt_msg) = rx.recv().await {
-            dbg!(&room_mgmt_msg);
             match room_mgmt_msg {
                 RoomMgmtMsg::CreateRoom {
                     creator_id,
+                    settings,
+                    test_params,
                     responder,
                 } => {
-                    let room_id = rand::random();
-                    let room = spawn_room(room_id, creator_id, self_tx.clone());
+                    // Ids are far too many to collide in practice, but a room is never replaced.
+                    let room_id = loop {
+                        let room_id = RoomId::generate();
+                        if !rooms.contains_key(&room_id) {
+                            break room_id;
+                        }
+                    };
+                    let room = spawn_room(
+                        room_id,
+                        creator_id,
+                        settings,
+                        test_params,
+                        self_tx.clone(),
+                        db.clone(),
+                        word_filter.clone(),
+                    );
                     rooms.insert(room_id, room);
                     responder.send(room_id).unwrap();
                 }
-                RoomMgmtMsg::JoinRoom { room_id, player } => {
-                    let room = rooms.get(&room_id).unwrap();
-                    room.send(RoomMsg::Join { player }).await.unwrap();
+                RoomMgmtMsg::JoinRoom {
+                    room_id,
+                    mut player,
+                    password,
+                } => {
+                    let Some(room) = rooms.get(&room_id) else {
+                        reject_room_not_found(&mut player.sender).await;
+                        continue;
+                    };
+                    // The room may have closed without having been forgotten yet.
+                    if let Err(mpsc::error::SendError(RoomMsg::Join { mut player, .. })) =
+                        room.send(RoomMsg::Join { player, password }).await
+                    {
+                        reject_room_not_found(&mut player.sender).await;
+                    }
+                }
+                RoomMgmtMsg::SpectateRoom {
+                    room_id,
+                    mut spectator,
+                    password,
+                } => {
+                    let Some(room) = rooms.get(&room_id) else {
+                        reject_room_not_found(&mut spectator.sender).await;
+                        continue;
+                    };
+                    if let Err(mpsc::error::SendError(RoomMsg::Spectate {
+                        mut spectator, ..
+                    })) = room
+                        .send(RoomMsg::Spectate {
+                            spectator,
+                            password,
+                        })
+                        .await
+                    {
+                        reject_room_not_found(&mut spectator.sender).await;
+                    }
+                }
+                RoomMgmtMsg::AddGhost {
+                    room_id,
+                    player_id,
+                    ghost,
+                    responder,
+                } => {
+                    let Some(room) = rooms.get(&room_id) else {
+                        let _ = responder.send(Err(AddGhostError::RoomNotFound));
+                        continue;
+                    };
+                    room.send(RoomMsg::AddGhost {
+                        player_id,
+                        ghost,
+                        responder,
+                    })
+                    .await
+                    .unwrap();
+                }
+                RoomMgmtMsg::UpdateListing { listing } => {
+                    let room_id = listing.room_id;
+                    if listing.n_players < listing.max_players {
+                        listings.insert(room_id, listing.clone());
+                        let _ = listings_tx.send(RoomListEvent::Update { room: listing });
+                    } else if listings.remove(&room_id).is_some() {
+                        let _ = listings_tx.send(RoomListEvent::Remove { room_id });
+                    }
+                }
+                RoomMgmtMsg::ListRooms { responder } => {
+                    let _ = responder.send(listings.values().cloned().collect());
+                }
+                RoomMgmtMsg::WatchRooms { responder } => {
+                    let rooms = listings.values().cloned().collect();
+                    let _ = responder.send((rooms, listings_tx.subscribe()));
                 }
                 RoomMgmtMsg::DeleteRoom { room_id } => {
                     rooms.remove(&room_id).unwrap();
+                    if listings.remove(&room_id).is_some() {
+                        let _ = listings_tx.send(RoomListEvent::Remove { room_id });
+                    }
                 }
             }
         }
@@ -120,11 +448,34 @@ pub fn spawn_room_manager() -> RoomMgr {
 pub enum RoomMgmtMsg {
     CreateRoom {
         creator_id: PlayerId,
+        settings: RoomSettings,
+        test_params: RandomTestParams,
         responder: oneshot::Sender<RoomId>,
     },
     JoinRoom {
         room_id: RoomId,
         player: Player,
+        password: Option<String>,
+    },
+    SpectateRoom {
+        room_id: RoomId,
+        spectator: Spectator,
+        password: Option<String>,
+    },
+    AddGhost {
+        room_id: RoomId,
+        player_id: PlayerId,
+        ghost: Ghost,
+        responder: oneshot::Sender<Result<(), AddGhostError>>,
+    },
+    UpdateListing {
+        listing: RoomListing,
+    },
+    ListRooms {
+        responder: oneshot::Sender<Vec<RoomListing>>,
+    },
+    WatchRooms {
+        responder: oneshot::Sender<(Vec<RoomListing>, broadcast::Receiver<RoomListEvent>)>,
     },
     DeleteRoom {
         room_id: RoomId,
@@ -134,8 +485,34 @@ pub enum RoomMgmtMsg {
 pub type RoomMgr = Sender<RoomMgmtMsg>;
 
 const ROOM_INACTIVITY_DURATION: Duration = Duration::from_secs(60);
+const MAX_PLAYERS_PER_ROOM: usize = 10;
+/// How many seconds players are given to prepare before every race, unless the room says otherwise.
+const DEFAULT_COUNTDOWN: u64 = 10;
+const MIN_COUNTDOWN: u64 = 3;
+const MAX_COUNTDOWN: u64 = 60;
+const MAX_ROUNDS: u32 = 20;
+const MAX_TEAMS: TeamId = 4;
+/// How long a round can go on for once its race starts, so that nobody can hold up the room.
+const ROUND_TIME_LIMIT: Duration = Duration::from_secs(300);
+/// How long players who lose their connection mid-race have to reconnect before they're out of it.
+const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+fn default_test_params() -> RandomTestParams {
+    RandomTestParams::Words {
+        language: "english".to_owned(),
+        length: 10,
+    }
+}
 
-fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
+fn spawn_room(
+    id: RoomId,
+    creator_id: PlayerId,
+    settings: RoomSettings,
+    mut test_params: RandomTestParams,
+    room_mgr: RoomMgr,
+    db: Db,
+    word_filter: WordFilter,
+) -> Room {
     let (tx, mut rx) = mpsc::channel(32);
 
     let room_tx = tx.clone();
@@ -143,17 +520,163 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
         let mut player_ids = Vec::<PlayerId>::new();
         let mut player_usernames = Vec::<String>::new();
         let mut senders = Vec::<PlayerTx>::new();
+        // Identifies the connection that each player is listened to on, so that a player who
+        // reconnects isn't made to leave when their old connection closes.
+        let mut connection_ids = Vec::<u32>::new();
         let mut player_states = Vec::<PlayerState>::new();
 
+        // Players who lost their connection mid-race, with the id of the request to put them out
+        // of the race if they don't reconnect in time.
+        let mut away_players = HashMap::<PlayerId, u32>::new();
+
+        // Spectators watch the room without taking part, and are only known by their connection.
+        let mut spectators = HashMap::<u32, PlayerTx>::new();
+
+        // A room can have at most one ghost, which is raced alongside the players.
+        let mut ghost: Option<(String, Ghost)> = None;
+        let mut ghost_replay: Option<JoinHandle<()>> = None;
+
+        // The kind of test raced in the room, which the creator and then the host picks. A ghost
+        // races its own test.
+
+        // The race in progress, which is saved once all of its players are done, along with the
+        // usernames of its players to award them points even if they leave.
+        let mut race: Option<RaceRecorder> = None;
+        let mut racer_usernames = HashMap::<PlayerId, String>::new();
+
+        // Rooms hold sessions of a number of rounds, with a race in every round. Points are
+        // awarded for every race and add up over the session.
+        let mut n_rounds_played: u32 = 0;
+        let mut scores = HashMap::<String, u32>::new();
+        let mut team_scores = HashMap::<TeamId, u32>::new();
+
+        // The teams that the host split players into, if any. Players keep their team if they
+        // leave and join again.
+        let mut teams = HashMap::<PlayerId, TeamId>::new();
+        // Identifies the round underway, if any, so that it can be timed out.
+        let mut round_id: Option<u32> = None;
+
         let mut host_id: Option<PlayerId> = None;
+        let mut banned_ids = HashSet::<PlayerId>::new();
+
+        let mut chat = Chat::new(word_filter);
         let mut delete_room_request_id: Option<u32> = None;
 
-        while let Some(room_msg) = rx.recv().await {
-            dbg!(id, &room_msg);
+        // What the room browser was last told of the room.
+        let mut listing: Option<RoomListing> = None;
+
+        loop {
+            // Rounds are over once nobody is racing anymore.
+            if round_id.is_some()
+                && !player_states.contains(&PlayerState::Racing)
+                && away_players.is_empty()
+            {
+                round_id = None;
+                n_rounds_played += 1;
+                if let Some(ghost_replay) = ghost_replay.take() {
+                    ghost_replay.abort();
+                }
+                if let Some(race) = race.take() {
+                    award_points(
+                        &race,
+                        &racer_usernames,
+                        &teams,
+                        &mut scores,
+                        &mut team_scores,
+                    );
+                    spawn_save_race(db.clone(), race);
+                }
+                racer_usernames.clear();
+                player_states.fill(PlayerState::NotReady);
+
+                let round_over_msg = serde_json::to_string(&ToPlayerMsg::RoundOver {
+                    n_rounds_played,
+                    scoreboard: scoreboard(&scores),
+                    team_scoreboard: team_scoreboard(&team_scores),
+                })
+                .unwrap();
+
+                let _ = join_all(
+                    senders
+                        .iter_mut()
+                        .chain(spectators.values_mut())
+                        .map(|sender| sender.send(Message::Text(round_over_msg.clone()))),
+                )
+                .await;
+
+                // A new session starts once the last round of the previous one is over.
+                if n_rounds_played == settings.n_rounds {
+                    n_rounds_played = 0;
+                    scores.clear();
+                    team_scores.clear();
+                }
+            }
+
+            // Public rooms keep the room browser up to date with whatever changes in them.
+            if !settings.is_private {
+                let new_listing = RoomListing {
+                    room_id: id,
+                    n_players: player_ids.len() + away_players.len(),
+                    max_players: settings.max_players,
+                    host: host_id
+                        .and_then(|host_id| player_ids.iter().position(|&id| id == host_id))
+                        .map(|host_pos| player_usernames[host_pos].clone()),
+                    test_params: test_params.clone(),
+                    phase: if player_states.iter().any(|state| {
+                        *state == PlayerState::Racing || *state == PlayerState::Finished
+                    }) {
+                        RoomPhase::Racing
+                    } else {
+                        RoomPhase::Waiting
+                    },
+                    has_password: settings.password.is_some(),
+                };
+                if listing.as_ref() != Some(&new_listing) {
+                    let _ = room_mgr
+                        .send(RoomMgmtMsg::UpdateListing {
+                            listing: new_listing.clone(),
+                        })
+                        .await;
+                    listing = Some(new_listing);
+                }
+            }
+
+            let Some(room_msg) = rx.recv().await else {
+                break;
+            };
             match room_msg {
-                RoomMsg::Join { mut player } => {
-                    let host_username = if player_ids.is_empty() || player.id == creator_id {
+                RoomMsg::Join {
+                    mut player,
+                    password,
+                } => {
+                    // Players who join again, such as after losing their connection, take over
+                    // their place in the room.
+                    let rejoined_idx = player_ids.iter().position(|&id| id == player.id);
+
+                    let is_known = rejoined_idx.is_some() || away_players.contains_key(&player.id);
+                    let error = if banned_ids.contains(&player.id) {
+                        Some(("Banned!", "The host has banned you from this room"))
+                    } else if player.id != creator_id && !settings.accepts(password.as_deref()) {
+                        Some((
+                            "Wrong password!",
+                            "This room needs the right password to join",
+                        ))
+                    } else if !is_known
+                        && player_ids.len() + away_players.len() >= settings.max_players
+                    {
+                        Some(("Room full!", "This room has no space left for more players"))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        reject(&mut player.sender, title, body).await;
+                        continue;
+                    }
+                    if rejoined_idx.is_none() && (player_ids.is_empty() || player.id == creator_id)
+                    {
                         host_id = Some(player.id);
+                    }
+                    let host_username = if host_id == Some(player.id) {
                         &player.username
                     } else {
                         let host_pos = player_ids
@@ -164,38 +687,153 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                     };
 
                     let init_msg = serde_json::to_string(&ToPlayerMsg::Init {
-                        other_players: zip(&player_usernames, &player_states)
-                            .into_iter()
-                            .map(|(username, state)| OtherPlayer {
+                        other_players: zip(&player_ids, zip(&player_usernames, &player_states))
+                            .enumerate()
+                            .filter(|&(i, _)| Some(i) != rejoined_idx)
+                            .map(|(_, (id, (username, state)))| OtherPlayer {
                                 username,
                                 state: *state,
+                                team: teams.get(id).copied(),
                             })
+                            .chain(ghost.iter().map(|(ghost_name, _)| OtherPlayer {
+                                username: ghost_name,
+                                state: PlayerState::Ready,
+                                team: None,
+                            }))
                             .collect(),
-                        host: host_username,
+                        host: Some(host_username),
+                        test_params: &test_params,
+                        settings: &settings,
+                        n_rounds_played,
+                        scoreboard: scoreboard(&scores),
+                        team_scoreboard: team_scoreboard(&team_scores),
+                        team: teams.get(&player.id).copied(),
+                        chat: chat.history().collect(),
                     })
                     .unwrap();
                     let join_msg = serde_json::to_string(&ToPlayerMsg::Join {
                         joining_player: &player.username,
                         is_host: *host_username == player.username,
+                        team: teams.get(&player.id).copied(),
                     })
                     .unwrap();
 
+                    let connection_id = rand::random();
                     let new_player_future = player.sender.send(Message::Text(init_msg));
-                    let other_player_futures = senders
-                        .iter_mut()
-                        .map(|sender: &mut PlayerTx| sender.send(Message::Text(join_msg.clone())));
-
-                    let _ = join(new_player_future, join_all(other_player_futures)).await;
+                    if let Some(player_idx) = rejoined_idx {
+                        let _ = new_player_future.await;
+                        senders[player_idx] = player.sender;
+                        connection_ids[player_idx] = connection_id;
+                    } else {
+                        let other_player_futures = senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender: &mut PlayerTx| {
+                                sender.send(Message::Text(join_msg.clone()))
+                            });
+
+                        let _ = join(new_player_future, join_all(other_player_futures)).await;
+
+                        // Players who come back in time carry on with their race.
+                        let state = match away_players.remove(&player.id) {
+                            Some(_) => PlayerState::Racing,
+                            None => PlayerState::NotReady,
+                        };
+
+                        player_ids.push(player.id);
+                        player_usernames.push(player.username);
+                        senders.push(player.sender);
+                        connection_ids.push(connection_id);
+                        player_states.push(state);
+                    }
 
-                    player_ids.push(player.id);
-                    player_usernames.push(player.username);
-                    senders.push(player.sender);
-                    player_states.push(PlayerState::NotReady);
+                    spawn_player_listener(
+                        player.id,
+                        connection_id,
+                        room_tx.clone(),
+                        player.receiver,
+                    );
 
-                    spawn_player_listener(player.id, room_tx.clone(), player.receiver);
+                    if let Some(race) = race.as_ref().filter(|race| race.is_racing(player.id)) {
+                        let player_idx = player_ids
+                            .iter()
+                            .position(|&id| id == player.id)
+                            .expect("player exists");
+                        resume_race(
+                            race,
+                            player_idx,
+                            &player_ids,
+                            &player_usernames,
+                            &teams,
+                            &mut senders,
+                            &mut spectators,
+                        )
+                        .await;
+                    }
 
                     delete_room_request_id = None;
                 }
+                RoomMsg::Spectate {
+                    spectator:
+                        Spectator {
+                            mut sender,
+                            receiver,
+                        },
+                    password,
+                } => {
+                    if !settings.accepts(password.as_deref()) {
+                        let error_msg = serde_json::to_string(&ToPlayerMsg::Error {
+                            title: "Wrong password!",
+                            body: "This room needs the right password to watch",
+                        })
+                        .unwrap();
+                        let _ = sender.send(Message::Text(error_msg)).await;
+                        let _ = sender.close().await;
+                        continue;
+                    }
+
+                    let host_username = host_id
+                        .and_then(|host_id| player_ids.iter().position(|&id| id == host_id))
+                        .map(|host_pos| player_usernames[host_pos].as_str());
+
+                    let init_msg = serde_json::to_string(&ToPlayerMsg::Init {
+                        other_players: zip(&player_ids, zip(&player_usernames, &player_states))
+                            .map(|(id, (username, state))| OtherPlayer {
+                                username,
+                                state: *state,
+                                team: teams.get(id).copied(),
+                            })
+                            .chain(ghost.iter().map(|(ghost_name, _)| OtherPlayer {
+                                username: ghost_name,
+                                state: PlayerState::Ready,
+                                team: None,
+                            }))
+                            .collect(),
+                        host: host_username,
+                        test_params: &test_params,
+                        settings: &settings,
+                        n_rounds_played,
+                        scoreboard: scoreboard(&scores),
+                        team_scoreboard: team_scoreboard(&team_scores),
+                        team: None,
+                        chat: chat.history().collect(),
+                    })
+                    .unwrap();
+                    if sender.send(Message::Text(init_msg)).await.is_err() {
+                        continue;
+                    }
+                    if let Some(race) = &race {
+                        let resume_msg = resume_msg(race, &player_ids, &player_usernames);
+                        let _ = sender.send(Message::Text(resume_msg)).await;
+                    }
+
+                    let spectator_id = rand::random();
+                    spectators.insert(spectator_id, sender);
+                    spawn_spectator_listener(spectator_id, room_tx.clone(), receiver);
+                }
+                RoomMsg::StopSpectating { spectator_id } => {
+                    spectators.remove(&spectator_id);
+                }
                 RoomMsg::Ready { player_id } => {
                     let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
                         continue;
@@ -226,7 +864,9 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                             .iter_mut()
                             .enumerate()
                             .filter(|(i, _)| *i != player_idx)
-                            .map(|(_, sender)| sender.send(Message::Text(ready_msg.clone()))),
+                            .map(|(_, sender)| sender)
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(ready_msg.clone()))),
                     )
                     .await
                     .into_iter();
@@ -261,7 +901,9 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                             .iter_mut()
                             .enumerate()
                             .filter(|(i, _)| *i != player_idx)
-                            .map(|(_, sender)| sender.send(Message::Text(not_ready_msg.clone()))),
+                            .map(|(_, sender)| sender)
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(not_ready_msg.clone()))),
                     )
                     .await
                     .into_iter();
@@ -281,20 +923,90 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                         continue;
                     }
 
+                    let error = if round_id.is_some() {
+                        Some(("Too late!", "A round is already underway"))
+                    } else if !player_states.contains(&PlayerState::Ready) {
+                        Some(("Nobody's ready!", "There must be someone ready to race"))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        let error_msg =
+                            serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    }
+
+                    let seeded_test_params = match &ghost {
+                        Some((_, ghost)) => ghost.test_params.clone(),
+                        None => test_params
+                            .seeded()
+                            .expect("Test params checked on being set"),
+                    };
+
+                    let new_racer_usernames: HashMap<PlayerId, String> =
+                        zip(&player_ids, &player_usernames)
+                            .zip(&player_states)
+                            .filter(|(_, state)| **state == PlayerState::Ready)
+                            .map(|((id, username), _)| (*id, username.clone()))
+                            .collect();
+                    let race_start = Instant::now() + settings.countdown;
+                    // A ghost's test may well have been taken down since it was raced.
+                    let Ok(new_race) = RaceRecorder::new(
+                        seeded_test_params.clone(),
+                        false,
+                        race_start,
+                        new_racer_usernames.keys().copied(),
+                    ) else {
+                        let error_msg = serde_json::to_string(&ToPlayerMsg::Error {
+                            title: "Invalid test!",
+                            body: "Races cannot be held with this test",
+                        })
+                        .unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    };
+
                     let prepare_msg = serde_json::to_string(&ToPlayerMsg::Prepare {
-                        time_until_race_start: Duration::from_secs(10),
-                        seed: rand::random(),
+                        time_until_race_start: settings.countdown,
+                        test_params: &seeded_test_params,
                     })
                     .unwrap();
 
+                    if let Some((_, ghost)) = &ghost {
+                        ghost_replay = Some(spawn_ghost_replay(
+                            ghost.timeline.progress.clone(),
+                            ghost.timeline.duration,
+                            settings.countdown,
+                            room_tx.clone(),
+                        ));
+                    }
+
                     let _ = join_all(
                         senders
                             .iter_mut()
+                            .chain(spectators.values_mut())
                             .map(|sender| sender.send(Message::Text(prepare_msg.clone()))),
                     )
                     .await
                     .into_iter();
 
+                    racer_usernames = new_racer_usernames;
+                    race = Some(new_race);
+
+                    let new_round_id = rand::random();
+                    round_id = Some(new_round_id);
+                    let room_tx = room_tx.clone();
+                    let time_limit = settings.countdown + ROUND_TIME_LIMIT;
+                    tokio::spawn(async move {
+                        sleep(time_limit).await;
+                        let _ = room_tx
+                            .send(RoomMsg::RoundTimeout {
+                                round_id: new_round_id,
+                            })
+                            .await;
+                    });
+
                     player_states
                         .iter_mut()
                         .filter(|state| **state == PlayerState::Ready)
@@ -302,20 +1014,110 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                             *state = PlayerState::Racing;
                         });
                 }
-                RoomMsg::Leave { player_id } => {
+                RoomMsg::SetTestParams {
+                    player_id,
+                    test_params: new_test_params,
+                } => {
+                    let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+
+                    let error = if host_id != Some(player_id) {
+                        Some(("You aren't the host!", "Only the host can change the test"))
+                    } else if player_states.iter().any(|state| {
+                        *state == PlayerState::Racing || *state == PlayerState::Finished
+                    }) {
+                        Some((
+                            "Too late!",
+                            "The racing phase has begun, the test cannot be changed now",
+                        ))
+                    } else if ghost.is_some() {
+                        Some((
+                            "The ghost decides!",
+                            "The test cannot be changed while racing a ghost",
+                        ))
+                    } else if !is_raceable(&new_test_params) {
+                        Some(("Invalid test!", "Races cannot be held with this test"))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        let error_msg =
+                            serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    }
+
+                    test_params = new_test_params;
+
+                    let test_params_msg = serde_json::to_string(&ToPlayerMsg::TestParams {
+                        test_params: &test_params,
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(test_params_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::Malformed {
+                    player_id,
+                    connection_id,
+                } => {
+                    let Some(index) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+                    if connection_ids[index] != connection_id {
+                        continue;
+                    }
+                    let error_msg = serde_json::to_string(&ToPlayerMsg::Error {
+                        title: "Invalid message!",
+                        body: "The server couldn't make sense of a message it was sent",
+                    })
+                    .unwrap();
+                    let _ = senders[index].send(Message::Text(error_msg)).await;
+                }
+                RoomMsg::Leave {
+                    player_id,
+                    connection_id,
+                } => {
                     let Some(index) = player_ids.iter().position(|id| *id == player_id) else {
                         continue;
                     };
+                    if connection_ids[index] != connection_id {
+                        continue;
+                    }
 
                     player_ids.remove(index);
                     let player_username = player_usernames.remove(index);
                     let _ = senders.remove(index);
+                    connection_ids.remove(index);
                     player_states.remove(index);
 
                     if player_id == host_id.expect("host assigned") {
                         host_id = None;
                     }
 
+                    // Players who leave mid-race are given some time to reconnect before they're
+                    // out of the race.
+                    if race.as_ref().is_some_and(|race| race.is_racing(player_id)) {
+                        let room_tx = room_tx.clone();
+                        let request_id = rand::random();
+                        away_players.insert(player_id, request_id);
+                        tokio::spawn(async move {
+                            sleep(RECONNECT_GRACE_PERIOD).await;
+                            let _ = room_tx
+                                .send(RoomMsg::ReconnectExpired {
+                                    player_id,
+                                    request_id,
+                                })
+                                .await;
+                        });
+                    }
+
                     if player_ids.is_empty() {
                         let room_tx = room_tx.clone();
                         let request_id = rand::random();
@@ -350,6 +1152,7 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                     let _ = join_all(
                         senders
                             .iter_mut()
+                            .chain(spectators.values_mut())
                             .map(|sender| sender.send(Message::Text(leave_msg.clone()))),
                     )
                     .await
@@ -363,9 +1166,20 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                         continue;
                     };
 
+                    // Progress is only passed on if it is fair.
+                    if !race
+                        .as_mut()
+                        .is_some_and(|race| race.update(player_id, progress))
+                    {
+                        continue;
+                    }
+
                     let update_msg = serde_json::to_string(&ToPlayerMsg::Update {
                         player: &player_usernames[player_idx],
                         progress,
+                        team_progress: race
+                            .as_ref()
+                            .and_then(|race| team_progress(race, &teams, player_id)),
                     })
                     .unwrap();
 
@@ -374,21 +1188,37 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                             .iter_mut()
                             .enumerate()
                             .filter(|(i, _)| *i != player_idx)
-                            .map(|(_, sender)| sender.send(Message::Text(update_msg.clone()))),
+                            .map(|(_, sender)| sender)
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(update_msg.clone()))),
                     )
                     .await
                     .into_iter();
                 }
-                RoomMsg::Finish {
-                    player_id,
-                    duration,
-                } => {
+                RoomMsg::Finish { player_id } => {
                     let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
                         continue;
                     };
+                    let Some(finish) = race.as_mut().map(|race| race.finish(player_id)) else {
+                        continue;
+                    };
+                    if finish == Err(FinishRejection::NotRacing) {
+                        continue;
+                    }
 
                     player_states[player_idx] = PlayerState::Finished;
 
+                    // Suspicious finishes are kept from the other players.
+                    let Ok(duration) = finish else {
+                        let error_msg = serde_json::to_string(&ToPlayerMsg::Error {
+                            title: "Suspicious finish!",
+                            body: "You couldn't have finished the race this quickly, so your finish doesn't count",
+                        })
+                        .unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    };
+
                     let finish_msg = serde_json::to_string(&ToPlayerMsg::Finish {
                         player: &player_usernames[player_idx],
                         duration,
@@ -400,12 +1230,386 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
                             .iter_mut()
                             .enumerate()
                             .filter(|(i, _)| *i != player_idx)
-                            .map(|(_, sender)| sender.send(Message::Text(finish_msg.clone()))),
+                            .map(|(_, sender)| sender)
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(finish_msg.clone()))),
                     )
                     .await
                     .into_iter();
                 }
-                RoomMsg::Delete { request_id } => {
+                RoomMsg::AddGhost {
+                    player_id,
+                    ghost: new_ghost,
+                    responder,
+                } => {
+                    if host_id != Some(player_id) {
+                        let _ = responder.send(Err(AddGhostError::NotHost));
+                        continue;
+                    }
+                    if ghost_replay.is_some()
+                        || player_states.iter().any(|state| {
+                            *state == PlayerState::Racing || *state == PlayerState::Finished
+                        })
+                    {
+                        let _ = responder.send(Err(AddGhostError::TooLate));
+                        continue;
+                    }
+
+                    let ghost_name = format!("{} (ghost)", new_ghost.username);
+                    let mut msgs = Vec::new();
+                    if let Some((old_ghost_name, _)) = &ghost {
+                        msgs.push(
+                            serde_json::to_string(&ToPlayerMsg::Leave {
+                                leaving_player: old_ghost_name,
+                                new_host: None,
+                            })
+                            .unwrap(),
+                        );
+                    }
+                    msgs.push(
+                        serde_json::to_string(&ToPlayerMsg::Join {
+                            joining_player: &ghost_name,
+                            is_host: false,
+                            team: None,
+                        })
+                        .unwrap(),
+                    );
+                    test_params = new_ghost.test_params.clone().into();
+                    msgs.push(
+                        serde_json::to_string(&ToPlayerMsg::TestParams {
+                            test_params: &test_params,
+                        })
+                        .unwrap(),
+                    );
+
+                    for msg in msgs {
+                        let _ = join_all(
+                            senders
+                                .iter_mut()
+                                .chain(spectators.values_mut())
+                                .map(|sender| sender.send(Message::Text(msg.clone()))),
+                        )
+                        .await;
+                    }
+
+                    ghost = Some((ghost_name, new_ghost));
+                    let _ = responder.send(Ok(()));
+                }
+                RoomMsg::GhostUpdate { progress } => {
+                    let Some((ghost_name, _)) = &ghost else {
+                        continue;
+                    };
+
+                    let update_msg = serde_json::to_string(&ToPlayerMsg::Update {
+                        player: ghost_name,
+                        progress,
+                        team_progress: None,
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(update_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::GhostFinish { duration } => {
+                    let Some((ghost_name, _)) = &ghost else {
+                        continue;
+                    };
+
+                    let finish_msg = serde_json::to_string(&ToPlayerMsg::Finish {
+                        player: ghost_name,
+                        duration,
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(finish_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::Kick {
+                    player_id,
+                    kicked_player,
+                    is_ban,
+                } => {
+                    let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+                    let kicked_idx = player_usernames
+                        .iter()
+                        .position(|username| *username == kicked_player)
+                        .filter(|&kicked_idx| kicked_idx != player_idx);
+
+                    let error = if host_id != Some(player_id) {
+                        Some(("You aren't the host!", "Only the host can kick players"))
+                    } else if kicked_idx.is_none() {
+                        Some((
+                            "No such player!",
+                            "There's nobody else by that name in the room",
+                        ))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        let error_msg =
+                            serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    }
+                    let kicked_idx = kicked_idx.expect("kicked player exists");
+                    let kicked_id = player_ids[kicked_idx];
+
+                    if is_ban {
+                        banned_ids.insert(kicked_id);
+                    }
+                    // Kicked players are out of the race at once rather than given time to
+                    // reconnect.
+                    if let Some(race) = &mut race {
+                        race.disconnect(kicked_id, DisconnectReason::Kicked);
+                    }
+
+                    let kick_msg = serde_json::to_string(&ToPlayerMsg::Kick {
+                        kicked_player: &player_usernames[kicked_idx],
+                        is_ban,
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(kick_msg.clone()))),
+                    )
+                    .await;
+                    let _ = senders[kicked_idx].close().await;
+
+                    // The kicked player then leaves the room like anyone else.
+                    let room_tx = room_tx.clone();
+                    let connection_id = connection_ids[kicked_idx];
+                    tokio::spawn(async move {
+                        let _ = room_tx
+                            .send(RoomMsg::Leave {
+                                player_id: kicked_id,
+                                connection_id,
+                            })
+                            .await;
+                    });
+                }
+                RoomMsg::TransferHost {
+                    player_id,
+                    new_host,
+                } => {
+                    let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+                    let new_host_idx = player_usernames
+                        .iter()
+                        .position(|username| *username == new_host)
+                        .filter(|&new_host_idx| new_host_idx != player_idx);
+
+                    let error = if host_id != Some(player_id) {
+                        Some((
+                            "You aren't the host!",
+                            "Only the host can hand over the room",
+                        ))
+                    } else if new_host_idx.is_none() {
+                        Some((
+                            "No such player!",
+                            "There's nobody else by that name in the room",
+                        ))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        let error_msg =
+                            serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    }
+                    let new_host_idx = new_host_idx.expect("new host exists");
+                    host_id = Some(player_ids[new_host_idx]);
+
+                    let host_msg = serde_json::to_string(&ToPlayerMsg::Host {
+                        new_host: &player_usernames[new_host_idx],
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(host_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::ReconnectExpired {
+                    player_id,
+                    request_id,
+                } => {
+                    if away_players.get(&player_id) != Some(&request_id) {
+                        continue;
+                    }
+                    away_players.remove(&player_id);
+
+                    if let Some(race) = &mut race {
+                        race.disconnect(player_id, DisconnectReason::Unknown);
+                    }
+                }
+                RoomMsg::SetTeam {
+                    player_id,
+                    player,
+                    team,
+                } => {
+                    let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+                    let team_player_idx = player_usernames
+                        .iter()
+                        .position(|username| *username == player);
+
+                    let error = if host_id != Some(player_id) {
+                        Some(("You aren't the host!", "Only the host can pick teams"))
+                    } else if round_id.is_some() {
+                        Some((
+                            "Too late!",
+                            "Teams cannot be changed while a round is underway",
+                        ))
+                    } else if team_player_idx.is_none() {
+                        Some(("No such player!", "There's nobody by that name in the room"))
+                    } else if team.is_some_and(|team| team >= MAX_TEAMS) {
+                        Some(("Invalid team!", "There can be at most 4 teams"))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        let error_msg =
+                            serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    }
+                    let team_player_idx = team_player_idx.expect("player exists");
+                    let team_player_id = player_ids[team_player_idx];
+
+                    match team {
+                        Some(team) => teams.insert(team_player_id, team),
+                        None => teams.remove(&team_player_id),
+                    };
+
+                    let team_msg = serde_json::to_string(&ToPlayerMsg::Team {
+                        player: &player_usernames[team_player_idx],
+                        team,
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(team_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::Chat { player_id, text } => {
+                    let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+
+                    let message = match chat.post(player_id, &player_usernames[player_idx], &text) {
+                        Ok(message) => message,
+                        Err(rejection) => {
+                            let (title, body) = rejection.describe();
+                            let error_msg =
+                                serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                            let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                            continue;
+                        }
+                    };
+                    let chat_msg = serde_json::to_string(&ToPlayerMsg::Chat { message }).unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(chat_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::Mute {
+                    player_id,
+                    muted_player,
+                    is_muted,
+                } => {
+                    let Some(player_idx) = player_ids.iter().position(|id| *id == player_id) else {
+                        continue;
+                    };
+                    let muted_idx = player_usernames
+                        .iter()
+                        .position(|username| *username == muted_player)
+                        .filter(|&muted_idx| muted_idx != player_idx);
+
+                    let error = if host_id != Some(player_id) {
+                        Some(("You aren't the host!", "Only the host can mute players"))
+                    } else if muted_idx.is_none() {
+                        Some((
+                            "No such player!",
+                            "There's nobody else by that name in the room",
+                        ))
+                    } else {
+                        None
+                    };
+                    if let Some((title, body)) = error {
+                        let error_msg =
+                            serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+                        let _ = senders[player_idx].send(Message::Text(error_msg)).await;
+                        continue;
+                    }
+                    let muted_idx = muted_idx.expect("muted player exists");
+                    chat.set_muted(player_ids[muted_idx], is_muted);
+
+                    let mute_msg = serde_json::to_string(&ToPlayerMsg::Mute {
+                        muted_player: &player_usernames[muted_idx],
+                        is_muted,
+                    })
+                    .unwrap();
+
+                    let _ = join_all(
+                        senders
+                            .iter_mut()
+                            .chain(spectators.values_mut())
+                            .map(|sender| sender.send(Message::Text(mute_msg.clone()))),
+                    )
+                    .await;
+                }
+                RoomMsg::RoundTimeout {
+                    round_id: timed_out_round_id,
+                } => {
+                    if round_id != Some(timed_out_round_id) {
+                        continue;
+                    }
+
+                    // Players still racing by now are out of the race, which ends the round.
+                    for (&player_id, state) in zip(&player_ids, &mut player_states) {
+                        if *state == PlayerState::Racing {
+                            *state = PlayerState::Finished;
+                            if let Some(race) = &mut race {
+                                race.disconnect(player_id, DisconnectReason::Timeout);
+                            }
+                        }
+                    }
+                    for (player_id, _) in away_players.drain() {
+                        if let Some(race) = &mut race {
+                            race.disconnect(player_id, DisconnectReason::Unknown);
+                        }
+                    }
+                }
+                RoomMsg::Delete { request_id } => {
                     if Some(request_id) == delete_room_request_id {
                         break;
                     }
@@ -413,6 +1617,13 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
             }
         }
 
+        if let Some(ghost_replay) = ghost_replay {
+            ghost_replay.abort();
+        }
+        if let Some(race) = race {
+            spawn_save_race(db, race);
+        }
+
         room_mgr
             .send(RoomMgmtMsg::DeleteRoom { room_id: id })
             .await
@@ -422,7 +1633,168 @@ fn spawn_room(id: RoomId, creator_id: PlayerId, room_mgr: RoomMgr) -> Room {
     tx
 }
 
-fn spawn_player_listener(player_id: u32, room: Room, mut receiver: PlayerRx) {
+// Awards points to the players of a finished race. Players who finished get a point for every
+// participant placed at or below them, while those who didn't finish get none.
+// Players on a team also add their points to their team's.
+fn award_points(
+    race: &RaceRecorder,
+    racer_usernames: &HashMap<PlayerId, String>,
+    teams: &HashMap<PlayerId, TeamId>,
+    scores: &mut HashMap<String, u32>,
+    team_scores: &mut HashMap<TeamId, u32>,
+) {
+    let placements = race.placements();
+    let n_participants = placements.len() as u32;
+    for (player_id, placement) in placements {
+        let has_finished = race
+            .progress(player_id)
+            .is_some_and(|(_, duration)| duration.is_some());
+        let Some(username) = racer_usernames.get(&player_id) else {
+            continue;
+        };
+        let points = if has_finished {
+            n_participants - placement + 1
+        } else {
+            0
+        };
+        *scores.entry(username.clone()).or_default() += points;
+        if let Some(&team) = teams.get(&player_id) {
+            *team_scores.entry(team).or_default() += points;
+        }
+    }
+}
+
+fn team_scoreboard(team_scores: &HashMap<TeamId, u32>) -> Vec<TeamScore> {
+    let mut team_scoreboard: Vec<TeamScore> = team_scores
+        .iter()
+        .map(|(&team, &points)| TeamScore { team, points })
+        .collect();
+    team_scoreboard.sort_by(|a, b| b.points.cmp(&a.points).then(a.team.cmp(&b.team)));
+    team_scoreboard
+}
+
+// The combined progress of a player's team in a race, if they're on one.
+fn team_progress(
+    race: &RaceRecorder,
+    teams: &HashMap<PlayerId, TeamId>,
+    player_id: PlayerId,
+) -> Option<u32> {
+    let team = teams.get(&player_id)?;
+    Some(
+        teams
+            .iter()
+            .filter(|&(_, other_team)| other_team == team)
+            .filter_map(|(&id, _)| race.progress(id))
+            .map(|(progress, _)| progress)
+            .sum(),
+    )
+}
+
+fn scoreboard(scores: &HashMap<String, u32>) -> Vec<Score<'_>> {
+    let mut scoreboard: Vec<Score> = scores
+        .iter()
+        .map(|(username, &points)| Score { username, points })
+        .collect();
+    scoreboard.sort_by(|a, b| b.points.cmp(&a.points).then(a.username.cmp(b.username)));
+    scoreboard
+}
+
+// Sends a player who has reconnected mid-race everything they need to carry on with it, and lets
+// the other players and spectators know how far they got.
+async fn resume_race(
+    race: &RaceRecorder,
+    player_idx: usize,
+    player_ids: &[PlayerId],
+    player_usernames: &[String],
+    teams: &HashMap<PlayerId, TeamId>,
+    senders: &mut [PlayerTx],
+    spectators: &mut HashMap<u32, PlayerTx>,
+) {
+    let player_id = player_ids[player_idx];
+    let resume_msg = resume_msg(race, player_ids, player_usernames);
+    let update_msg = serde_json::to_string(&ToPlayerMsg::Update {
+        player: &player_usernames[player_idx],
+        progress: race.progress(player_id).map_or(0, |(progress, _)| progress),
+        team_progress: team_progress(race, teams, player_id),
+    })
+    .unwrap();
+
+    let _ = senders[player_idx].send(Message::Text(resume_msg)).await;
+    let _ = join_all(
+        senders
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| *i != player_idx)
+            .map(|(_, sender)| sender)
+            .chain(spectators.values_mut())
+            .map(|sender| sender.send(Message::Text(update_msg.clone()))),
+    )
+    .await;
+}
+
+fn resume_msg(race: &RaceRecorder, player_ids: &[PlayerId], player_usernames: &[String]) -> String {
+    let now = Instant::now();
+    let players: Vec<RacerProgress> = zip(player_ids, player_usernames)
+        .filter_map(|(&id, username)| {
+            let (progress, duration) = race.progress(id)?;
+            Some(RacerProgress {
+                username,
+                progress,
+                duration,
+            })
+        })
+        .collect();
+
+    serde_json::to_string(&ToPlayerMsg::Resume {
+        test_params: race.test_params(),
+        time_until_race_start: race.start().saturating_duration_since(now),
+        elapsed: now.saturating_duration_since(race.start()),
+        players,
+    })
+    .unwrap()
+}
+
+/// Tells whoever is on the other end of a connection why they can't be let in, and closes it.
+async fn reject(sender: &mut PlayerTx, title: &str, body: &str) {
+    let error_msg = serde_json::to_string(&ToPlayerMsg::Error { title, body }).unwrap();
+    let _ = sender.send(Message::Text(error_msg)).await;
+    let _ = sender.close().await;
+}
+
+async fn reject_room_not_found(sender: &mut PlayerTx) {
+    reject(
+        sender,
+        "No such room!",
+        "This room doesn't exist, or has closed",
+    )
+    .await;
+}
+
+// Replays a ghost's progress on its original timeline, starting from when the race starts.
+fn spawn_ghost_replay(
+    progress: Vec<ProgressPoint>,
+    duration: u32,
+    countdown: Duration,
+    room: Room,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let race_start = tokio::time::Instant::now() + countdown;
+        let at = |elapsed: u32| race_start + Duration::from_millis(elapsed.into());
+
+        for ProgressPoint(elapsed, progress) in progress {
+            tokio::time::sleep_until(at(elapsed)).await;
+            if room.send(RoomMsg::GhostUpdate { progress }).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::time::sleep_until(at(duration)).await;
+        let duration = Duration::from_millis(duration.into());
+        let _ = room.send(RoomMsg::GhostFinish { duration }).await;
+    })
+}
+
+fn spawn_player_listener(player_id: u32, connection_id: u32, room: Room, mut receiver: PlayerRx) {
     tokio::spawn(async move {
         while let Some(result) = receiver.next().await {
             let Ok(msg) = result else {
@@ -431,13 +1803,29 @@ fn spawn_player_listener(player_id: u32, room: Room, mut receiver: PlayerRx) {
             let Message::Text(msg) = msg else {
                 continue;
             };
-            let msg: FromPlayerMsg = serde_json::from_str(msg.as_str()).unwrap();
+            let Ok(msg) = serde_json::from_str::<FromPlayerMsg>(msg.as_str()) else {
+                room.send(RoomMsg::Malformed {
+                    player_id,
+                    connection_id,
+                })
+                .await
+                .unwrap();
+                continue;
+            };
             match msg {
                 FromPlayerMsg::Ready {} => room.send(RoomMsg::Ready { player_id }).await.unwrap(),
                 FromPlayerMsg::NotReady {} => {
                     room.send(RoomMsg::NotReady { player_id }).await.unwrap()
                 }
                 FromPlayerMsg::Start {} => room.send(RoomMsg::Start { player_id }).await.unwrap(),
+                FromPlayerMsg::SetTestParams { test_params } => {
+                    room.send(RoomMsg::SetTestParams {
+                        player_id,
+                        test_params,
+                    })
+                    .await
+                    .unwrap();
+                }
                 FromPlayerMsg::Update { progress } => {
                     room.send(RoomMsg::Update {
                         player_id,
@@ -446,25 +1834,176 @@ fn spawn_player_listener(player_id: u32, room: Room, mut receiver: PlayerRx) {
                     .await
                     .unwrap();
                 }
-                FromPlayerMsg::Finish { duration } => {
-                    room.send(RoomMsg::Finish {
+                FromPlayerMsg::Finish {} => {
+                    room.send(RoomMsg::Finish { player_id }).await.unwrap();
+                }
+                FromPlayerMsg::Kick { player } => {
+                    room.send(RoomMsg::Kick {
                         player_id,
-                        duration,
+                        kicked_player: player,
+                        is_ban: false,
                     })
                     .await
                     .unwrap();
                 }
+                FromPlayerMsg::Ban { player } => {
+                    room.send(RoomMsg::Kick {
+                        player_id,
+                        kicked_player: player,
+                        is_ban: true,
+                    })
+                    .await
+                    .unwrap();
+                }
+                FromPlayerMsg::SetTeam { player, team } => {
+                    room.send(RoomMsg::SetTeam {
+                        player_id,
+                        player,
+                        team,
+                    })
+                    .await
+                    .unwrap();
+                }
+                FromPlayerMsg::Chat { text } => {
+                    room.send(RoomMsg::Chat { player_id, text }).await.unwrap();
+                }
+                FromPlayerMsg::Mute { player } => {
+                    room.send(RoomMsg::Mute {
+                        player_id,
+                        muted_player: player,
+                        is_muted: true,
+                    })
+                    .await
+                    .unwrap();
+                }
+                FromPlayerMsg::Unmute { player } => {
+                    room.send(RoomMsg::Mute {
+                        player_id,
+                        muted_player: player,
+                        is_muted: false,
+                    })
+                    .await
+                    .unwrap();
+                }
+                FromPlayerMsg::TransferHost { player } => {
+                    room.send(RoomMsg::TransferHost {
+                        player_id,
+                        new_host: player,
+                    })
+                    .await
+                    .unwrap();
+                }
+            }
+        }
+
+        room.send(RoomMsg::Leave {
+            player_id,
+            connection_id,
+        })
+        .await
+        .unwrap();
+    });
+}
+
+// Spectators can't do anything in the room, so their connection is only listened to for when it
+// closes.
+fn spawn_spectator_listener(spectator_id: u32, room: Room, mut receiver: PlayerRx) {
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = receiver.next().await {
+            if let Message::Close(_) = msg {
+                break;
             }
         }
 
-        room.send(RoomMsg::Leave { player_id }).await.unwrap();
+        let _ = room.send(RoomMsg::StopSpectating { spectator_id }).await;
     });
 }
 
-pub type RoomId = u32;
+/// Settings picked by the creator of a room, which hold for as long as the room lives.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomSettings {
+    max_players: usize,
+    is_private: bool,
+    /// Never sent to players, who must already know it to be in the room.
+    #[serde(skip_serializing)]
+    password: Option<String>,
+    countdown: Duration,
+    n_rounds: u32,
+}
+
+impl RoomSettings {
+    fn accepts(&self, password: Option<&str>) -> bool {
+        self.password.is_none() || self.password.as_deref() == password
+    }
+}
+
+/// What the room browser shows of a public room.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomListing {
+    room_id: RoomId,
+    /// Includes players who lost their connection mid-race and may come back.
+    n_players: usize,
+    max_players: usize,
+    host: Option<String>,
+    test_params: RandomTestParams,
+    phase: RoomPhase,
+    has_password: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum RoomPhase {
+    Waiting,
+    Racing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(rename_all_fields = "camelCase")]
+#[serde(tag = "kind", content = "payload")]
+pub enum RoomListEvent {
+    /// Sent first to watchers of the room browser, with every room listed in it.
+    Init { rooms: Vec<RoomListing> },
+
+    /// Sent when a room is listed, or when anything about a listed room changes.
+    Update { room: RoomListing },
+
+    /// Sent when a room is deleted, or can't be joined anymore.
+    Remove { room_id: RoomId },
+}
+
+/// Rooms can only be joined by those who know their id, so ids are random enough not to be guessed.
+/// They're sent as hex strings, since they don't fit in a JavaScript number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct RoomId(u128);
+
+impl RoomId {
+    fn generate() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl From<RoomId> for String {
+    fn from(RoomId(id): RoomId) -> Self {
+        format!("{id:032x}")
+    }
+}
+
+impl TryFrom<String> for RoomId {
+    type Error = std::num::ParseIntError;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        u128::from_str_radix(&id, 16).map(Self)
+    }
+}
 
 type PlayerId = u32;
 
+type TeamId = u32;
+
 type Room = Sender<RoomMsg>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
@@ -476,16 +2015,103 @@ enum PlayerState {
     Finished,
 }
 
+pub struct Spectator {
+    sender: PlayerTx,
+    receiver: PlayerRx,
+}
+
+impl Debug for Spectator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Spectator")
+    }
+}
+
 #[derive(Debug)]
 enum RoomMsg {
-    Join { player: Player },
-    Ready { player_id: u32 },
-    NotReady { player_id: u32 },
-    Start { player_id: u32 },
-    Leave { player_id: u32 },
-    Update { player_id: u32, progress: u32 },
-    Finish { player_id: u32, duration: Duration },
-    Delete { request_id: u32 },
+    Join {
+        player: Player,
+        password: Option<String>,
+    },
+    Spectate {
+        spectator: Spectator,
+        password: Option<String>,
+    },
+    StopSpectating {
+        spectator_id: u32,
+    },
+    Ready {
+        player_id: u32,
+    },
+    NotReady {
+        player_id: u32,
+    },
+    Start {
+        player_id: u32,
+    },
+    SetTestParams {
+        player_id: u32,
+        test_params: RandomTestParams,
+    },
+    /// Sent when a player sends a message that can't be parsed.
+    Malformed {
+        player_id: u32,
+        connection_id: u32,
+    },
+    Leave {
+        player_id: u32,
+        connection_id: u32,
+    },
+    Kick {
+        player_id: u32,
+        kicked_player: String,
+        is_ban: bool,
+    },
+    TransferHost {
+        player_id: u32,
+        new_host: String,
+    },
+    ReconnectExpired {
+        player_id: u32,
+        request_id: u32,
+    },
+    SetTeam {
+        player_id: u32,
+        player: String,
+        team: Option<TeamId>,
+    },
+    Chat {
+        player_id: u32,
+        text: String,
+    },
+    Mute {
+        player_id: u32,
+        muted_player: String,
+        is_muted: bool,
+    },
+    RoundTimeout {
+        round_id: u32,
+    },
+    Update {
+        player_id: u32,
+        progress: u32,
+    },
+    Finish {
+        player_id: u32,
+    },
+    AddGhost {
+        player_id: u32,
+        ghost: Ghost,
+        responder: oneshot::Sender<Result<(), AddGhostError>>,
+    },
+    GhostUpdate {
+        progress: u32,
+    },
+    GhostFinish {
+        duration: Duration,
+    },
+    Delete {
+        request_id: u32,
+    },
 }
 
 #[derive(Debug, Serialize)]
@@ -493,16 +2119,27 @@ enum RoomMsg {
 #[serde(rename_all_fields = "camelCase")]
 #[serde(tag = "kind", content = "payload")]
 enum ToPlayerMsg<'a> {
-    /// Sent to players when they join the room.
+    /// Sent to players when they join the room, and to spectators when they start watching.
     Init {
         other_players: Vec<OtherPlayer<'a>>,
-        host: &'a str,
+        /// Only absent for spectators of rooms that nobody has joined yet.
+        host: Option<&'a str>,
+        test_params: &'a RandomTestParams,
+        settings: &'a RoomSettings,
+        n_rounds_played: u32,
+        scoreboard: Vec<Score<'a>>,
+        team_scoreboard: Vec<TeamScore>,
+        /// The team of the player themselves.
+        team: Option<TeamId>,
+        /// The latest messages in the room's chat, oldest first.
+        chat: Vec<&'a ChatMessage>,
     },
 
     /// Sent to players when another player joins the room.
     Join {
         joining_player: &'a String,
         is_host: bool,
+        team: Option<TeamId>,
     },
 
     /// Sent to players when another player leaves the room.
@@ -512,20 +2149,53 @@ enum ToPlayerMsg<'a> {
         new_host: Option<&'a String>,
     },
 
+    /// Sent to players when the host kicks or bans a player, including the kicked player.
+    Kick {
+        kicked_player: &'a String,
+        is_ban: bool,
+    },
+
+    /// Sent to players when the host hands over the room to another player.
+    Host { new_host: &'a String },
+
     /// Sent to players when another player becomes ready.
     Ready { ready_player: &'a String },
 
     /// Sent to players when another player becomes not ready.
     NotReady { not_ready_player: &'a String },
 
+    /// Sent to players when the host changes the test, or a ghost with its own test is added.
+    TestParams { test_params: &'a RandomTestParams },
+
     /// Sent to players when all players are ready.
     Prepare {
         time_until_race_start: Duration,
-        seed: Seed,
+        test_params: &'a SeededTestParams,
+    },
+
+    /// Sent to players who reconnect mid-race and spectators who start watching mid-race, with how
+    /// far everyone in the room got.
+    Resume {
+        test_params: &'a SeededTestParams,
+        time_until_race_start: Duration,
+        elapsed: Duration,
+        players: Vec<RacerProgress<'a>>,
+    },
+
+    /// Sent to players when the host puts a player on a team or takes them off it.
+    Team {
+        player: &'a String,
+        team: Option<TeamId>,
     },
 
-    /// Sent to players when another player progresses in the race.
-    Update { player: &'a String, progress: u32 },
+    /// Sent to players when another player progresses in the race, along with the combined
+    /// progress of their team if they're on one.
+    Update {
+        player: &'a String,
+        progress: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        team_progress: Option<u32>,
+    },
 
     /// Sent to players when another player completes the race.
     Finish {
@@ -533,6 +2203,24 @@ enum ToPlayerMsg<'a> {
         duration: Duration,
     },
 
+    /// Sent to players when every player of a round is done, with the points of the session so
+    /// far. The session is over once as many rounds as the room's settings say have been played.
+    RoundOver {
+        n_rounds_played: u32,
+        scoreboard: Vec<Score<'a>>,
+        team_scoreboard: Vec<TeamScore>,
+    },
+
+    /// Sent to players when anyone in the room posts a message in its chat, including the player
+    /// who posted it, since words in it may have been filtered.
+    Chat { message: &'a ChatMessage },
+
+    /// Sent to players when the host mutes or unmutes a player in the chat.
+    Mute {
+        muted_player: &'a String,
+        is_muted: bool,
+    },
+
     /// Sent to players when an error happens.
     Error { title: &'a str, body: &'a str },
 }
@@ -541,6 +2229,27 @@ enum ToPlayerMsg<'a> {
 struct OtherPlayer<'a> {
     username: &'a str,
     state: PlayerState,
+    team: Option<TeamId>,
+}
+
+#[derive(Debug, Serialize)]
+struct Score<'a> {
+    username: &'a str,
+    points: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct TeamScore {
+    team: TeamId,
+    points: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct RacerProgress<'a> {
+    username: &'a str,
+    progress: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<Duration>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -551,6 +2260,235 @@ enum FromPlayerMsg {
     Ready {},
     NotReady {},
     Start {},
-    Update { progress: u32 },
-    Finish { duration: Duration },
+    SetTestParams {
+        test_params: RandomTestParams,
+    },
+    Update {
+        progress: u32,
+    },
+    /// The duration is measured by the server, so any claimed by the player is ignored.
+    Finish {},
+    Kick {
+        player: String,
+    },
+    Ban {
+        player: String,
+    },
+    TransferHost {
+        player: String,
+    },
+    /// Puts a player on a team, or takes them off their team if none is given.
+    SetTeam {
+        player: String,
+        team: Option<TeamId>,
+    },
+    Chat {
+        text: String,
+    },
+    Mute {
+        player: String,
+    },
+    Unmute {
+        player: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Races that started long enough ago for any progress to be fair.
+    fn race(test_params: SeededTestParams, player_ids: &[PlayerId]) -> RaceRecorder {
+        let start = Instant::now() - Duration::from_secs(60);
+        RaceRecorder::new(test_params, false, start, player_ids.iter().copied()).unwrap()
+    }
+
+    fn words_race(player_ids: &[PlayerId]) -> RaceRecorder {
+        let test_params = SeededTestParams::Words {
+            language: "english".to_owned(),
+            length: 10,
+            seed: [1, 2, 3, 4],
+        };
+        race(test_params, player_ids)
+    }
+
+    fn time_race(player_ids: &[PlayerId]) -> RaceRecorder {
+        let test_params = SeededTestParams::Time {
+            language: "english".to_owned(),
+            duration: 15,
+            seed: [1, 2, 3, 4],
+        };
+        race(test_params, player_ids)
+    }
+
+    fn finish(race: &mut RaceRecorder, player_id: PlayerId, progress: u32) {
+        assert!(race.update(player_id, progress));
+        race.finish(player_id).unwrap();
+    }
+
+    fn usernames(player_ids: &[PlayerId]) -> HashMap<PlayerId, String> {
+        player_ids
+            .iter()
+            .map(|&id| (id, format!("player{id}")))
+            .collect()
+    }
+
+    fn points(scores: &HashMap<String, u32>) -> Vec<(&str, u32)> {
+        scoreboard(scores)
+            .iter()
+            .map(|score| (score.username, score.points))
+            .collect()
+    }
+
+    #[test]
+    fn awards_points_by_placement() {
+        let player_ids = [1, 2, 3, 4];
+        let mut race = words_race(&player_ids);
+        finish(&mut race, 3, 9);
+        finish(&mut race, 1, 9);
+        finish(&mut race, 2, 9);
+        race.disconnect(4, DisconnectReason::Timeout);
+
+        let mut scores = HashMap::new();
+        award_points(
+            &race,
+            &usernames(&player_ids),
+            &HashMap::new(),
+            &mut scores,
+            &mut HashMap::new(),
+        );
+        // Players who didn't finish still show up on the scoreboard.
+        assert_eq!(
+            points(&scores),
+            [
+                ("player3", 4),
+                ("player1", 3),
+                ("player2", 2),
+                ("player4", 0)
+            ],
+        );
+    }
+
+    #[test]
+    fn awards_tied_players_the_same_points() {
+        let player_ids = [1, 2, 3];
+        let mut race = time_race(&player_ids);
+        finish(&mut race, 1, 20);
+        finish(&mut race, 2, 30);
+        finish(&mut race, 3, 30);
+
+        let mut scores = HashMap::new();
+        award_points(
+            &race,
+            &usernames(&player_ids),
+            &HashMap::new(),
+            &mut scores,
+            &mut HashMap::new(),
+        );
+        // Ties on the scoreboard are listed by username.
+        assert_eq!(
+            points(&scores),
+            [("player2", 3), ("player3", 3), ("player1", 1)],
+        );
+    }
+
+    #[test]
+    fn adds_up_points_over_rounds() {
+        let mut scores = HashMap::new();
+
+        let mut race = words_race(&[1, 2]);
+        finish(&mut race, 1, 9);
+        finish(&mut race, 2, 9);
+        award_points(
+            &race,
+            &usernames(&[1, 2]),
+            &HashMap::new(),
+            &mut scores,
+            &mut HashMap::new(),
+        );
+
+        // Players can join between rounds, and racers who left are skipped.
+        let mut race = words_race(&[1, 2, 3]);
+        finish(&mut race, 2, 9);
+        finish(&mut race, 3, 9);
+        finish(&mut race, 1, 9);
+        award_points(
+            &race,
+            &usernames(&[2, 3]),
+            &HashMap::new(),
+            &mut scores,
+            &mut HashMap::new(),
+        );
+
+        assert_eq!(
+            points(&scores),
+            [("player2", 4), ("player1", 2), ("player3", 2)],
+        );
+    }
+
+    fn team_points(team_scores: &HashMap<TeamId, u32>) -> Vec<(TeamId, u32)> {
+        team_scoreboard(team_scores)
+            .iter()
+            .map(|score| (score.team, score.points))
+            .collect()
+    }
+
+    #[test]
+    fn adds_up_the_points_of_teams() {
+        let player_ids = [1, 2, 3, 4, 5];
+        // Player 5 isn't on a team.
+        let teams = HashMap::from([(1, 0), (2, 1), (3, 0), (4, 1)]);
+        let mut scores = HashMap::new();
+        let mut team_scores = HashMap::new();
+
+        let mut race = words_race(&player_ids);
+        finish(&mut race, 1, 9);
+        finish(&mut race, 2, 9);
+        finish(&mut race, 5, 9);
+        finish(&mut race, 4, 9);
+        race.disconnect(3, DisconnectReason::Timeout);
+        award_points(
+            &race,
+            &usernames(&player_ids),
+            &teams,
+            &mut scores,
+            &mut team_scores,
+        );
+        assert_eq!(team_points(&team_scores), [(1, 6), (0, 5)]);
+
+        let mut race = words_race(&player_ids);
+        finish(&mut race, 2, 9);
+        finish(&mut race, 1, 9);
+        finish(&mut race, 3, 9);
+        finish(&mut race, 5, 9);
+        finish(&mut race, 4, 9);
+        award_points(
+            &race,
+            &usernames(&player_ids),
+            &teams,
+            &mut scores,
+            &mut team_scores,
+        );
+        // Tied teams are listed by team.
+        assert_eq!(team_points(&team_scores), [(0, 12), (1, 12)]);
+    }
+
+    #[test]
+    fn combines_the_progress_of_teams() {
+        let teams = HashMap::from([(1, 0), (2, 1), (3, 0)]);
+        let mut race = words_race(&[1, 2, 3, 4]);
+        assert!(race.update(1, 3));
+        assert!(race.update(2, 5));
+        assert!(race.update(3, 4));
+        assert!(race.update(4, 8));
+
+        assert_eq!(team_progress(&race, &teams, 1), Some(7));
+        assert_eq!(team_progress(&race, &teams, 3), Some(7));
+        assert_eq!(team_progress(&race, &teams, 2), Some(5));
+        assert_eq!(team_progress(&race, &teams, 4), None);
+
+        // Players keep counting towards their team's progress once they've finished.
+        finish(&mut race, 3, 9);
+        assert_eq!(team_progress(&race, &teams, 1), Some(12));
+    }
 }
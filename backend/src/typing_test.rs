@@ -1,8 +1,21 @@
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::preferences::QuoteModeLength;
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod content;
+
+pub mod gen;
+pub use gen::generate;
+
+pub mod prng;
+
+pub mod replay;
+pub use replay::Replay;
+
+pub mod stat;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(rename_all_fields = "camelCase")]
 #[serde(tag = "mode", content = "params")]
@@ -12,7 +25,7 @@ pub enum RandomTestParams {
     Quote { length: QuoteModeLength },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(rename_all_fields = "camelCase")]
 #[serde(tag = "mode", content = "params")]
@@ -35,6 +48,40 @@ pub enum SeededTestParams {
 
 pub type Seed = [i32; 4];
 
+impl RandomTestParams {
+    /// Picks a random test with these params, provided that the language or quote length is known.
+    pub fn seeded(&self) -> Option<SeededTestParams> {
+        match self {
+            Self::Words { language, length } => {
+                content::words(language)?;
+                Some(SeededTestParams::Words {
+                    language: language.to_owned(),
+                    length: *length,
+                    seed: rand::random(),
+                })
+            }
+            Self::Time { language, duration } => {
+                content::words(language)?;
+                Some(SeededTestParams::Time {
+                    language: language.to_owned(),
+                    duration: *duration,
+                    seed: rand::random(),
+                })
+            }
+            Self::Quote { length } => {
+                let (first, last) = content::quotes().range(length);
+                if first >= last {
+                    return None;
+                }
+                Some(SeededTestParams::Quote {
+                    length: *length,
+                    id: rand::thread_rng().gen_range(first..last),
+                })
+            }
+        }
+    }
+}
+
 impl From<Vec<u8>> for RandomTestParams {
     fn from(value: Vec<u8>) -> Self {
         serde_json::from_slice(value.as_slice()).expect("Well-formed serialization")
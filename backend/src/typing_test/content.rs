@@ -0,0 +1,96 @@
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::preferences::QuoteModeLength;
+
+// The word lists and quotes are embedded from the frontend's static content so that both sides
+// always generate tests from exactly the same data.
+
+static ENGLISH: Lazy<Vec<String>> = Lazy::new(|| {
+    parse(include_str!(
+        "../../../frontend/src/static/words/english.json"
+    ))
+});
+static ENGLISH_1K: Lazy<Vec<String>> = Lazy::new(|| {
+    parse(include_str!(
+        "../../../frontend/src/static/words/english1k.json"
+    ))
+});
+static ENGLISH_5K: Lazy<Vec<String>> = Lazy::new(|| {
+    parse(include_str!(
+        "../../../frontend/src/static/words/english5k.json"
+    ))
+});
+static ENGLISH_10K: Lazy<Vec<String>> = Lazy::new(|| {
+    parse(include_str!(
+        "../../../frontend/src/static/words/english10k.json"
+    ))
+});
+static ENGLISH_25K: Lazy<Vec<String>> = Lazy::new(|| {
+    parse(include_str!(
+        "../../../frontend/src/static/words/english25k.json"
+    ))
+});
+
+static QUOTES: Lazy<Quotes> =
+    Lazy::new(|| parse(include_str!("../../../frontend/src/static/quotes.json")));
+
+fn parse<T: for<'de> Deserialize<'de>>(json: &str) -> T {
+    serde_json::from_str(json).expect("Well-formed static content")
+}
+
+/// Returns the list of words for the given language, if it is known.
+pub fn words(language: &str) -> Option<&'static [String]> {
+    let words = match language {
+        "english" => &*ENGLISH,
+        "english1k" => &*ENGLISH_1K,
+        "english5k" => &*ENGLISH_5K,
+        "english10k" => &*ENGLISH_10K,
+        "english25k" => &*ENGLISH_25K,
+        _ => return None,
+    };
+    Some(words.as_slice())
+}
+
+pub fn quotes() -> &'static Quotes {
+    &QUOTES
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quotes {
+    short: (usize, usize),
+    medium: (usize, usize),
+    long: (usize, usize),
+    very_long: (usize, usize),
+    all: (usize, usize),
+    quotes: Vec<Quote>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Quote {
+    pub text: String,
+}
+
+impl Quotes {
+    /// Returns the quote with the given id, if it falls in the range of quotes for the given
+    /// length.
+    pub fn get(&self, length: &QuoteModeLength, id: usize) -> Option<&Quote> {
+        let (first, last) = self.range(length);
+        if (first..last).contains(&id) {
+            self.quotes.get(id)
+        } else {
+            None
+        }
+    }
+
+    pub fn range(&self, length: &QuoteModeLength) -> (usize, usize) {
+        match length {
+            QuoteModeLength::Short => self.short,
+            QuoteModeLength::Medium => self.medium,
+            QuoteModeLength::Long => self.long,
+            QuoteModeLength::VeryLong => self.very_long,
+            QuoteModeLength::All => self.all,
+        }
+    }
+}
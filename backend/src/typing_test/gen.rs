@@ -0,0 +1,127 @@
+use super::{content, prng::Sfc32, Seed, SeededTestParams};
+
+/// Upper bound on the number of keystrokes per second a human can sustain (roughly 400 WPM). Every
+/// word takes at least one keystroke, so this also bounds the number of words typed per second.
+pub const MAX_KEYSTROKES_PER_SEC: u32 = 35;
+
+/// Generates the words of the test described by the given params, exactly as the frontend would.
+///
+/// Time mode tests are unbounded, so enough words are generated that no one could possibly type
+/// past the end of them in the given duration. Since the words are drawn from a seeded PRNG, this is
+/// always a prefix-compatible extension of what the frontend generated.
+///
+/// Fails if the language or quote is unknown.
+pub fn generate(test_params: &SeededTestParams) -> Result<Vec<String>, GenerateError> {
+    match test_params {
+        SeededTestParams::Words {
+            language,
+            length,
+            seed,
+        } => generate_words(*seed, language, *length as usize),
+        SeededTestParams::Time {
+            language,
+            duration,
+            seed,
+        } => generate_words(*seed, language, time_mode_length(*duration)),
+        SeededTestParams::Quote { length, id } => content::quotes()
+            .get(length, *id)
+            .map(|quote| quote.text.split(' ').map(str::to_owned).collect())
+            .ok_or(GenerateError::UnknownQuote),
+    }
+}
+
+/// Port of the frontend's `randomWords` (see `frontend/src/typing-test/gen.ts`).
+pub fn generate_words(
+    seed: Seed,
+    language: &str,
+    count: usize,
+) -> Result<Vec<String>, GenerateError> {
+    let words = content::words(language).ok_or(GenerateError::UnknownLanguage)?;
+    Ok(random_words(seed, words, count))
+}
+
+pub fn random_words(seed: Seed, words: &[String], count: usize) -> Vec<String> {
+    let mut rand = Sfc32::new(seed);
+    (0..count)
+        .map(|_| words[rand.next_u32() as usize % words.len()].to_owned())
+        .collect()
+}
+
+fn time_mode_length(duration: u32) -> usize {
+    // Same padding as the frontend's `TimedTypingTest`.
+    const PADDING: u32 = 60;
+    duration
+        .saturating_mul(MAX_KEYSTROKES_PER_SEC)
+        .saturating_add(PADDING) as usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerateError {
+    UnknownLanguage,
+    UnknownQuote,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preferences::QuoteModeLength;
+
+    // The expected words were produced by the frontend's `randomWords` with the same seeds.
+
+    #[test]
+    fn generates_the_same_words_as_the_frontend() {
+        let test_params = SeededTestParams::Words {
+            language: "english".to_owned(),
+            length: 10,
+            seed: [1, 2, 3, 4],
+        };
+        assert_eq!(
+            generate(&test_params).unwrap(),
+            ["have", "who", "that", "way", "never", "hold", "begin", "it", "general", "much"],
+        );
+        assert_eq!(
+            generate_words([-1, i32::MIN, i32::MAX, 123456789], "english1k", 10).unwrap(),
+            ["please", "animal", "saw", "chord", "cat", "who", "told", "iron", "plain", "went"],
+        );
+    }
+
+    #[test]
+    fn time_mode_tests_extend_the_words_generated_by_the_frontend() {
+        let test_params = SeededTestParams::Time {
+            language: "english".to_owned(),
+            duration: 15,
+            seed: [1, 2, 3, 4],
+        };
+        let test = generate(&test_params).unwrap();
+        assert_eq!(test.len(), time_mode_length(15));
+        assert_eq!(test[..4], ["have", "who", "that", "way"]);
+    }
+
+    #[test]
+    fn generates_quotes_split_like_the_frontend() {
+        let test_params = SeededTestParams::Quote {
+            length: QuoteModeLength::Short,
+            id: 0,
+        };
+        assert_eq!(
+            generate(&test_params).unwrap(),
+            ["One", "and", "one", "and", "one", "is", "three."],
+        );
+    }
+
+    #[test]
+    fn fails_on_unknown_tests() {
+        let test_params = SeededTestParams::Words {
+            language: "klingon".to_owned(),
+            length: 10,
+            seed: [1, 2, 3, 4],
+        };
+        assert_eq!(generate(&test_params), Err(GenerateError::UnknownLanguage));
+
+        let test_params = SeededTestParams::Quote {
+            length: QuoteModeLength::Short,
+            id: usize::MAX,
+        };
+        assert_eq!(generate(&test_params), Err(GenerateError::UnknownQuote));
+    }
+}
@@ -0,0 +1,64 @@
+use super::Seed;
+
+/// Port of the frontend's `sfc32` (see `frontend/src/util/prng.ts`). Takes in a 128-bit seed and
+/// produces 32-bit unsigned integers, matching the frontend bit for bit.
+#[derive(Debug, Clone)]
+pub struct Sfc32 {
+    a: u32,
+    b: u32,
+    c: u32,
+    d: u32,
+}
+
+impl Sfc32 {
+    pub fn new(seed: Seed) -> Self {
+        let [a, b, c, d] = seed.map(|x| x as u32);
+        Self { a, b, c, d }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut t = self.a.wrapping_add(self.b);
+        self.a = self.b ^ (self.b >> 9);
+        self.b = self.c.wrapping_add(self.c << 3);
+        self.c = self.c.rotate_left(21);
+        self.d = self.d.wrapping_add(1);
+        t = t.wrapping_add(self.d);
+        self.c = self.c.wrapping_add(t);
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The expected outputs were produced by the frontend's `sfc32` with the same seeds.
+
+    fn outputs(seed: Seed) -> Vec<u32> {
+        let mut rand = Sfc32::new(seed);
+        (0..8).map(|_| rand.next_u32()).collect()
+    }
+
+    #[test]
+    fn matches_the_frontend() {
+        assert_eq!(
+            outputs([0, 0, 0, 0]),
+            [1, 2, 12, 18874399, 56669315, 2581679515, 3653559774, 1064146342],
+        );
+        assert_eq!(
+            outputs([1, 2, 3, 4]),
+            [8, 35, 56623210, 207756683, 3469086937, 2920252771, 1846779140, 1194183609],
+        );
+    }
+
+    #[test]
+    fn matches_the_frontend_with_negative_seeds() {
+        assert_eq!(
+            outputs([-1, i32::MIN, i32::MAX, 123456789]),
+            [
+                2270940437, 127651086, 1220936404, 3300464774, 3940613214, 1361108481, 2566850619,
+                1980519255,
+            ],
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    gen::MAX_KEYSTROKES_PER_SEC,
+    generate,
+    stat::{
+        calculate_char_counts, calculate_stats, char_len, get_actual_test, is_test_done,
+        CharCounts, Stats,
+    },
+    SeededTestParams,
+};
+
+const MAX_WORDS_MODE_LENGTH: u32 = 1000;
+const MAX_TIME_MODE_DURATION: u32 = 600;
+const MAX_KEYSTROKES: usize = 50_000;
+
+/// Number of consecutive characters over which the typing speed is checked against
+/// [`MAX_KEYSTROKES_PER_SEC`]. Checking over a window rather than between every pair of keystrokes
+/// allows for the near-simultaneous key presses that happen when typing fast.
+const SPEED_CHECK_WINDOW: usize = 20;
+
+/// Number of characters the frontend allows typing past the end of a word.
+const MAX_EXTRA_CHARS: usize = 20;
+
+/// A single change to the test input, serialized compactly as `[elapsed, deleted, inserted]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystroke(
+    /// Milliseconds elapsed since the first keystroke of the test.
+    pub u32,
+    /// Number of characters removed from the end of the input.
+    pub u32,
+    /// Characters then appended to the end of the input.
+    pub String,
+);
+
+/// The test that was attempted, along with every keystroke made during the attempt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Replay {
+    pub test_params: SeededTestParams,
+    pub keystrokes: Vec<Keystroke>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    UnsupportedTest,
+    TooManyKeystrokes,
+    NonMonotonicTimestamps,
+    ImplausibleSpeed,
+    InputTooLong,
+    Incomplete,
+}
+
+impl ReplayError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::UnsupportedTest => "Test cannot be verified",
+            Self::TooManyKeystrokes => "Too many keystrokes",
+            Self::NonMonotonicTimestamps => "Keystroke timestamps are out of order",
+            Self::ImplausibleSpeed => "Keystrokes are too fast to be humanly possible",
+            Self::InputTooLong => "Input is longer than the test allows",
+            Self::Incomplete => "Test was not completed",
+        }
+    }
+}
+
+/// How far along the test the typist was over the course of the test.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timeline {
+    /// Every change in progress (the number of words typed, as reported in typing races).
+    pub progress: Vec<ProgressPoint>,
+    /// Milliseconds taken to complete the test.
+    pub duration: u32,
+}
+
+/// A change in progress, serialized compactly as `[elapsed, progress]`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ProgressPoint(
+    /// Milliseconds elapsed since the first keystroke of the test.
+    pub u32,
+    /// Number of words typed.
+    pub u32,
+);
+
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    pub stats: Stats,
+    pub timeline: Timeline,
+}
+
+impl Replay {
+    /// Replays the keystrokes against the test exactly like the frontend processes input and
+    /// computes the resulting stats, along with the typist's progress over time.
+    pub fn evaluate(&self) -> Result<Evaluation, ReplayError> {
+        let time_limit = match self.test_params {
+            SeededTestParams::Words { length, .. } if length > MAX_WORDS_MODE_LENGTH => {
+                return Err(ReplayError::UnsupportedTest)
+            }
+            SeededTestParams::Time { duration, .. } if duration > MAX_TIME_MODE_DURATION => {
+                return Err(ReplayError::UnsupportedTest)
+            }
+            SeededTestParams::Time { duration, .. } => Some(duration * 1000),
+            _ => None,
+        };
+
+        if self.keystrokes.is_empty() {
+            return Err(ReplayError::Incomplete);
+        }
+        if self.keystrokes.len() > MAX_KEYSTROKES {
+            return Err(ReplayError::TooManyKeystrokes);
+        }
+        check_timings(&self.keystrokes)?;
+
+        let test = generate(&self.test_params).map_err(|_| ReplayError::UnsupportedTest)?;
+
+        // The input is kept split into words as it changes, rather than split again after every
+        // keystroke.
+        let mut attempt = vec![String::new()];
+        let mut char_counts = CharCounts::default();
+        let mut progress = Vec::new();
+        let mut end = None;
+
+        for Keystroke(elapsed, deleted, inserted) in &self.keystrokes {
+            if time_limit.is_some_and(|time_limit| *elapsed > time_limit) {
+                break;
+            }
+
+            let previous_len = attempt.len();
+            let previous_last_word_len = char_len(attempt.last().expect("attempt is non-empty"));
+
+            for _ in 0..*deleted {
+                let last_word = attempt.last_mut().expect("attempt is non-empty");
+                if last_word.pop().is_none() {
+                    if attempt.len() == 1 {
+                        break;
+                    }
+                    // Deleting the space before the last word.
+                    attempt.pop();
+                }
+            }
+            for c in inserted.chars() {
+                if c == ' ' {
+                    attempt.push(String::new());
+                } else {
+                    attempt.last_mut().expect("attempt is non-empty").push(c);
+                }
+                check_input_length(&test, &attempt)?;
+            }
+
+            char_counts = calculate_char_counts(
+                &test,
+                &attempt,
+                previous_len,
+                previous_last_word_len,
+                char_counts,
+            );
+
+            let words_typed = attempt.len().min(test.len() + 1) as u32 - 1;
+            if progress
+                .last()
+                .is_none_or(|&ProgressPoint(_, last)| last != words_typed)
+            {
+                progress.push(ProgressPoint(*elapsed, words_typed));
+            }
+
+            if time_limit.is_none() && is_test_done(&test, &attempt) {
+                end = Some(*elapsed);
+                break;
+            }
+        }
+
+        let duration = match (time_limit, end) {
+            (Some(time_limit), _) => time_limit,
+            (None, Some(end)) if end > 0 => end,
+            _ => return Err(ReplayError::Incomplete),
+        };
+        if char_counts == CharCounts::default() {
+            return Err(ReplayError::Incomplete);
+        }
+
+        let stats = calculate_stats(
+            &get_actual_test(&test, &attempt),
+            &attempt,
+            duration as f64 / 1000.0,
+            char_counts,
+        );
+        Ok(Evaluation {
+            stats,
+            timeline: Timeline { progress, duration },
+        })
+    }
+}
+
+// Rejects input that the frontend wouldn't have accepted: words with too many extra characters, or
+// more words than the test has.
+fn check_input_length(test: &[String], attempt: &[String]) -> Result<(), ReplayError> {
+    let last_index = attempt.len() - 1;
+    let too_long = match test.get(last_index) {
+        Some(word) => char_len(&attempt[last_index]) > char_len(word) + MAX_EXTRA_CHARS,
+        None => attempt.len() > test.len() + 1,
+    };
+    if too_long {
+        Err(ReplayError::InputTooLong)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_timings(keystrokes: &[Keystroke]) -> Result<(), ReplayError> {
+    // Timestamps of the most recently typed characters, oldest first.
+    let mut window = VecDeque::with_capacity(SPEED_CHECK_WINDOW + 1);
+    let mut last_elapsed = 0;
+
+    for Keystroke(elapsed, _, inserted) in keystrokes {
+        if *elapsed < last_elapsed {
+            return Err(ReplayError::NonMonotonicTimestamps);
+        }
+        last_elapsed = *elapsed;
+
+        for _ in inserted.chars() {
+            window.push_back(*elapsed);
+            if window.len() > SPEED_CHECK_WINDOW {
+                window.pop_front();
+            }
+            if window.len() == SPEED_CHECK_WINDOW {
+                let span = window.back().expect("window is full")
+                    - window.front().expect("window is full");
+                let min_span = (SPEED_CHECK_WINDOW as u32 - 1) * 1000 / MAX_KEYSTROKES_PER_SEC;
+                if span < min_span {
+                    return Err(ReplayError::ImplausibleSpeed);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preferences::QuoteModeLength;
+
+    const QUOTE: SeededTestParams = SeededTestParams::Quote {
+        length: QuoteModeLength::Short,
+        id: 0,
+    };
+
+    fn time_mode(duration: u32) -> SeededTestParams {
+        SeededTestParams::Time {
+            language: "english".to_owned(),
+            duration,
+            seed: [1, 2, 3, 4],
+        }
+    }
+
+    // Types out the text one character at a time, at the given interval in milliseconds. A `<`
+    // stands for a backspace.
+    fn type_out(text: &str, interval: u32) -> Vec<Keystroke> {
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| match c {
+                '<' => Keystroke(i as u32 * interval, 1, String::new()),
+                c => Keystroke(i as u32 * interval, 0, c.to_string()),
+            })
+            .collect()
+    }
+
+    fn evaluate(
+        test_params: SeededTestParams,
+        keystrokes: Vec<Keystroke>,
+    ) -> Result<Evaluation, ReplayError> {
+        Replay {
+            test_params,
+            keystrokes,
+        }
+        .evaluate()
+    }
+
+    // The expected stats are those the frontend shows for the same input.
+
+    #[test]
+    fn computes_the_same_stats_as_the_frontend() {
+        let Evaluation { stats, timeline } =
+            evaluate(QUOTE, type_out("Onw<e and one and one is three.", 100)).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                wpm: 116.0,
+                raw_wpm: 116.0,
+                accuracy: 96.67,
+            },
+        );
+        assert_eq!(timeline.duration, 3000);
+        assert_eq!(
+            timeline
+                .progress
+                .iter()
+                .map(|&ProgressPoint(elapsed, words)| (elapsed, words))
+                .collect::<Vec<_>>(),
+            [
+                (0, 0),
+                (500, 1),
+                (900, 2),
+                (1300, 3),
+                (1700, 4),
+                (2100, 5),
+                (2400, 6)
+            ],
+        );
+    }
+
+    #[test]
+    fn keeps_the_attempt_across_deleted_spaces_and_multiple_characters() {
+        let mut keystrokes = vec![
+            Keystroke(0, 0, "One an".to_owned()),
+            Keystroke(1000, 4, "e and".to_owned()),
+        ];
+        keystrokes.extend(type_out(" one and one is three.", 100).into_iter().map(
+            |Keystroke(elapsed, deleted, inserted)| Keystroke(elapsed + 1100, deleted, inserted),
+        ));
+        let Evaluation { stats, timeline } = evaluate(QUOTE, keystrokes).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                wpm: 108.75,
+                raw_wpm: 108.75,
+                accuracy: 100.0,
+            },
+        );
+        assert_eq!(timeline.duration, 3200);
+    }
+
+    #[test]
+    fn stops_time_mode_tests_at_the_time_limit() {
+        let mut keystrokes = type_out("have who", 200);
+        keystrokes.push(Keystroke(15_001, 0, " that".to_owned()));
+        let Evaluation { stats, timeline } = evaluate(time_mode(15), keystrokes).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                wpm: 6.4,
+                raw_wpm: 6.4,
+                accuracy: 100.0,
+            },
+        );
+        assert_eq!(timeline.duration, 15_000);
+    }
+
+    #[test]
+    fn rejects_unfinished_tests() {
+        assert_eq!(
+            evaluate(QUOTE, type_out("One and", 100)).unwrap_err(),
+            ReplayError::Incomplete,
+        );
+        assert_eq!(
+            evaluate(QUOTE, Vec::new()).unwrap_err(),
+            ReplayError::Incomplete,
+        );
+    }
+
+    #[test]
+    fn rejects_typing_too_fast_over_the_speed_window() {
+        // 35 keystrokes per second is a keystroke every 28.6ms.
+        assert!(evaluate(QUOTE, type_out("One and one and one is three.", 29)).is_ok());
+        assert_eq!(
+            evaluate(QUOTE, type_out("One and one and one is three.", 28)).unwrap_err(),
+            ReplayError::ImplausibleSpeed,
+        );
+
+        // A burst of near-simultaneous key presses is fine as long as the window isn't too fast.
+        let mut keystrokes = type_out("One and one and one is three.", 100);
+        keystrokes[1].0 = keystrokes[0].0;
+        assert!(evaluate(QUOTE, keystrokes).is_ok());
+    }
+
+    #[test]
+    fn rejects_timestamps_that_go_backwards() {
+        let mut keystrokes = type_out("One and one and one is three.", 100);
+        keystrokes.swap(3, 4);
+        assert_eq!(
+            evaluate(QUOTE, keystrokes).unwrap_err(),
+            ReplayError::NonMonotonicTimestamps,
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_keystrokes() {
+        let keystrokes = (0..=MAX_KEYSTROKES as u32)
+            .map(|i| Keystroke(i * 100, 0, "a".to_owned()))
+            .collect();
+        assert_eq!(
+            evaluate(time_mode(60), keystrokes).unwrap_err(),
+            ReplayError::TooManyKeystrokes,
+        );
+    }
+
+    #[test]
+    fn rejects_input_longer_than_the_frontend_allows() {
+        let word = format!("One{}", "e".repeat(MAX_EXTRA_CHARS));
+        assert!(evaluate(
+            QUOTE,
+            type_out(&format!("{word}<< and one and one is three."), 100)
+        )
+        .is_ok());
+        assert_eq!(
+            evaluate(QUOTE, type_out(&format!("{word}e"), 100)).unwrap_err(),
+            ReplayError::InputTooLong,
+        );
+    }
+}
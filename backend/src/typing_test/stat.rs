@@ -0,0 +1,139 @@
+//! Port of the frontend's result calculations (see `frontend/src/typing-test/stat.ts`).
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub wpm: f32,
+    pub raw_wpm: f32,
+    pub accuracy: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharCounts {
+    pub correct_chars: u32,
+    pub incorrect_chars: u32,
+}
+
+/// Computes the stats of an attempt that lasted the given duration (in seconds).
+pub fn calculate_stats(
+    test: &[String],
+    attempt: &[String],
+    duration: f64,
+    char_counts: CharCounts,
+) -> Stats {
+    Stats {
+        wpm: compute_wpm(test, attempt, duration),
+        raw_wpm: compute_raw_wpm(attempt, duration),
+        accuracy: compute_accuracy(char_counts),
+    }
+}
+
+/// Counts the character typed by a change to the attempt. Only the number of words in the previous
+/// attempt and the length of its last word are needed, so the previous attempt needn't be kept.
+pub fn calculate_char_counts(
+    test: &[String],
+    new_attempt: &[String],
+    previous_len: usize,
+    previous_last_word_len: usize,
+    char_counts: CharCounts,
+) -> CharCounts {
+    let CharCounts {
+        mut correct_chars,
+        mut incorrect_chars,
+    } = char_counts;
+
+    let last_index = new_attempt.len() - 1;
+    if new_attempt.len() == previous_len
+        && char_len(&new_attempt[last_index]) > previous_last_word_len
+    {
+        let typed_char = new_attempt[last_index].chars().last();
+        let expected_char = test
+            .get(last_index)
+            .and_then(|word| word.chars().nth(char_len(&new_attempt[last_index]) - 1));
+        if typed_char.is_some() && typed_char == expected_char {
+            correct_chars += 1;
+        } else {
+            incorrect_chars += 1;
+        }
+    } else if new_attempt.len() > previous_len {
+        if test.get(new_attempt.len() - 2) == new_attempt.get(new_attempt.len() - 2) {
+            correct_chars += 1;
+        } else {
+            incorrect_chars += 1;
+        }
+    }
+
+    CharCounts {
+        correct_chars,
+        incorrect_chars,
+    }
+}
+
+/// Helper method that cuts out the extra words at the end of the generated test. It also cuts out
+/// extra letters at the end of the last word typed.
+pub fn get_actual_test(test: &[String], attempt: &[String]) -> Vec<String> {
+    if attempt.len() > test.len() {
+        return test.to_vec();
+    }
+    let mut actual_test = test[..attempt.len()].to_vec();
+    let last = attempt.len() - 1;
+    actual_test[last] = actual_test[last]
+        .chars()
+        .take(char_len(&attempt[last]))
+        .collect();
+    actual_test
+}
+
+pub fn is_test_done(test: &[String], attempt: &[String]) -> bool {
+    (test.len() == attempt.len() && test.last() == attempt.last()) || test.len() < attempt.len()
+}
+
+// To compute the WPM, only the number of characters typed for correctly typed words is
+// considered.
+fn compute_wpm(test: &[String], attempt: &[String], duration: f64) -> f32 {
+    let mut char_count = 0;
+    for (i, word) in test.iter().enumerate().take(test.len().saturating_sub(1)) {
+        if Some(word) == attempt.get(i) {
+            char_count += char_len(word) + 1;
+        }
+    }
+    if test.last().is_some() && test.last() == attempt.last() {
+        char_count += char_len(test.last().expect("test is non-empty"));
+    }
+    compute_wpm_helper(char_count, duration)
+}
+
+// To compute raw WPM, only the number of characters typed is considered. There is no check for
+// correctly typed characters or words.
+fn compute_raw_wpm(attempt: &[String], duration: f64) -> f32 {
+    let mut char_count = 0;
+    for word in attempt {
+        char_count += char_len(word);
+    }
+    char_count += attempt.len().saturating_sub(1); // Counting spaces.
+    compute_wpm_helper(char_count, duration)
+}
+
+fn compute_accuracy(
+    CharCounts {
+        correct_chars,
+        incorrect_chars,
+    }: CharCounts,
+) -> f32 {
+    let accuracy = correct_chars as f64 / (correct_chars + incorrect_chars) as f64;
+    round_to_two_decimal_places(100.0 * accuracy)
+}
+
+// Computes WPM given the number of characters typed and the duration in seconds.
+fn compute_wpm_helper(char_count: usize, duration: f64) -> f32 {
+    round_to_two_decimal_places((60 * char_count) as f64 / (5.0 * duration))
+}
+
+fn round_to_two_decimal_places(number: f64) -> f32 {
+    ((number * 100.0).round() / 100.0) as f32
+}
+
+// The frontend measures lengths in UTF-16 code units, but the word lists and quotes only contain
+// characters for which that is the same as the number of chars.
+pub fn char_len(word: &str) -> usize {
+    word.chars().count()
+}
@@ -1,3 +1,10 @@
+DROP TABLE IF EXISTS race_participant;
+DROP TABLE IF EXISTS race;
+DROP TABLE IF EXISTS rating;
+DROP TABLE IF EXISTS identity;
+DROP TABLE IF EXISTS session;
+DROP TABLE IF EXISTS email_token;
+DROP TABLE IF EXISTS ghost;
 DROP TABLE IF EXISTS stat;
 DROP TABLE IF EXISTS result;
 DROP TABLE IF EXISTS user;
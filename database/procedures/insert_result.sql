@@ -6,12 +6,17 @@ CREATE OR REPLACE PROCEDURE insert_result
     test_completed_timestamp TIMESTAMP,
     wpm FLOAT UNSIGNED,
     raw_wpm FLOAT UNSIGNED,
-    accuracy FLOAT UNSIGNED)
+    accuracy FLOAT UNSIGNED,
+    verified BOOLEAN)
 MODIFIES SQL DATA
 BEGIN
+    DECLARE result_id INT UNSIGNED;
+
     INSERT INTO result 
-        (user_id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy)
-    VALUES (user_id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy);
+        (user_id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy, verified)
+    VALUES (user_id, test_params, test_completed_timestamp, wpm, raw_wpm, accuracy, verified);
+
+    SET result_id = LAST_INSERT_ID();
 
     INSERT INTO stat VALUES
         (user_id, test_params, wpm, raw_wpm, accuracy, wpm, raw_wpm, accuracy, 1)
@@ -24,6 +29,10 @@ BEGIN
         sum_raw_wpm = sum_raw_wpm + raw_wpm,
         sum_accuracy = sum_accuracy + accuracy,
         n_results = n_results + 1;
+
+    SELECT result_id AS id, stat.best_wpm = wpm AS is_personal_best
+    FROM stat
+    WHERE stat.user_id = user_id AND stat.test_params = test_params;
 END
 
 //
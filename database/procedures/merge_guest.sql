@@ -0,0 +1,48 @@
+DELIMITER //
+
+CREATE OR REPLACE PROCEDURE merge_guest
+    (guest_id INT UNSIGNED,
+    account_id INT UNSIGNED)
+MODIFIES SQL DATA
+BEGIN
+    -- Everything is carried over or nothing is, so that results aren't left split between the two.
+    DECLARE EXIT HANDLER FOR SQLEXCEPTION
+    BEGIN
+        ROLLBACK;
+        RESIGNAL;
+    END;
+
+    START TRANSACTION;
+
+    UPDATE result SET user_id = account_id WHERE user_id = guest_id;
+
+    INSERT INTO stat
+        SELECT account_id, test_params, best_wpm, best_raw_wpm, best_accuracy, sum_wpm, sum_raw_wpm, sum_accuracy, n_results
+        FROM stat AS guest_stat
+        WHERE guest_stat.user_id = guest_id
+    ON DUPLICATE KEY UPDATE
+        -- best_wpm should be set at the end since it is used in the condition.
+        best_raw_wpm = IF(VALUES(best_wpm) > best_wpm, VALUES(best_raw_wpm), best_raw_wpm),
+        best_accuracy = IF(VALUES(best_wpm) > best_wpm, VALUES(best_accuracy), best_accuracy),
+        best_wpm = IF(VALUES(best_wpm) > best_wpm, VALUES(best_wpm), best_wpm),
+        sum_wpm = sum_wpm + VALUES(sum_wpm),
+        sum_raw_wpm = sum_raw_wpm + VALUES(sum_raw_wpm),
+        sum_accuracy = sum_accuracy + VALUES(sum_accuracy),
+        n_results = n_results + VALUES(n_results);
+
+    -- The guest's races become the account's, except those that the account took part in too.
+    UPDATE IGNORE race_participant SET user_id = account_id WHERE user_id = guest_id;
+
+    -- The account keeps its own ratings, and only takes the guest's in modes it hasn't raced in.
+    INSERT IGNORE INTO rating
+        SELECT account_id, test_params, rating, deviation, volatility, n_races
+        FROM rating AS guest_rating
+        WHERE guest_rating.user_id = guest_id;
+
+    DELETE FROM user WHERE id = guest_id AND is_guest;
+
+    COMMIT;
+END
+
+//
+DELIMITER ;
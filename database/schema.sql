@@ -1,9 +1,10 @@
 CREATE TABLE `user` (
   id INT UNSIGNED auto_increment PRIMARY KEY,
   username VARCHAR(30) NOT NULL UNIQUE,
-  email VARCHAR(256) NOT NULL UNIQUE,
-  salt VARBINARY(32) NOT NULL,
-  password_hash VARBINARY(32) NOT NULL,
+  email VARCHAR(256) UNIQUE,
+  salt VARBINARY(32),
+  password_hash VARBINARY(255),
+  is_guest BOOLEAN NOT NULL DEFAULT FALSE,
   preferences JSON NOT NULL,
   created_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
@@ -16,6 +17,7 @@ CREATE TABLE `result` (
   wpm FLOAT UNSIGNED NOT NULL,
   raw_wpm FLOAT UNSIGNED NOT NULL,
   accuracy FLOAT UNSIGNED NOT NULL,
+  verified BOOLEAN NOT NULL DEFAULT FALSE,
   FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE ON UPDATE RESTRICT,
   UNIQUE KEY (test_params, test_completed_timestamp, wpm, raw_wpm, accuracy)
 );
@@ -34,6 +36,88 @@ CREATE TABLE `stat` (
   PRIMARY KEY (user_id, test_params(100))
 );
 
+CREATE TABLE `ghost` (
+  id CHAR(32) PRIMARY KEY,
+  result_id INT UNSIGNED NOT NULL UNIQUE,
+  test_params JSON NOT NULL,
+  timeline JSON NOT NULL,
+  expiry_timestamp TIMESTAMP NULL,
+  FOREIGN KEY (result_id) REFERENCES result (id) ON DELETE CASCADE ON UPDATE RESTRICT
+);
+
+CREATE TABLE `email_token` (
+  id INT UNSIGNED auto_increment PRIMARY KEY,
+  purpose VARCHAR(20) NOT NULL,
+  email VARCHAR(256) NOT NULL,
+  token_hash BINARY(32) NOT NULL,
+  failed_attempts INT UNSIGNED NOT NULL DEFAULT 0,
+  consumed BOOLEAN NOT NULL DEFAULT FALSE,
+  created_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  expiry_timestamp TIMESTAMP NOT NULL
+);
+
+CREATE TABLE `session` (
+  id CHAR(32) PRIMARY KEY,
+  user_id INT UNSIGNED NOT NULL,
+  created_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_active_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE ON UPDATE RESTRICT
+);
+
+CREATE TABLE `identity` (
+  id INT UNSIGNED auto_increment PRIMARY KEY,
+  provider VARCHAR(30) NOT NULL,
+  subject VARCHAR(255) NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  created_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (provider, subject),
+  FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE ON UPDATE RESTRICT
+);
+
+CREATE TABLE `rating` (
+  user_id INT UNSIGNED NOT NULL,
+  test_params JSON NOT NULL,
+  rating DOUBLE NOT NULL,
+  deviation DOUBLE NOT NULL,
+  volatility DOUBLE NOT NULL,
+  n_races INT UNSIGNED NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE ON UPDATE RESTRICT,
+  PRIMARY KEY (user_id, test_params(100))
+);
+
+CREATE TABLE `race` (
+  id INT UNSIGNED auto_increment PRIMARY KEY,
+  test_params JSON NOT NULL,
+  is_rated BOOLEAN NOT NULL,
+  started_timestamp TIMESTAMP NOT NULL,
+  finished_timestamp TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE `race_participant` (
+  race_id INT UNSIGNED NOT NULL,
+  user_id INT UNSIGNED NOT NULL,
+  placement INT UNSIGNED NOT NULL,
+  finish_duration INT UNSIGNED,
+  disconnect_reason VARCHAR(20),
+  is_flagged BOOLEAN NOT NULL DEFAULT FALSE,
+  FOREIGN KEY (race_id) REFERENCES race (id) ON DELETE CASCADE ON UPDATE RESTRICT,
+  FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE ON UPDATE RESTRICT,
+  PRIMARY KEY (race_id, user_id)
+);
+
 CREATE INDEX `ix_result_user_id` ON `result` (user_id);
 
+CREATE INDEX `ix_result_leaderboard` ON `result` (verified, test_params(100), test_completed_timestamp);
+
+CREATE INDEX `ix_ghost_expiry_timestamp` ON `ghost` (expiry_timestamp);
+
+CREATE INDEX `ix_email_token_purpose_email` ON `email_token` (purpose, email);
+
+CREATE INDEX `ix_session_user_id` ON `session` (user_id);
+
+CREATE INDEX `ix_session_last_active_timestamp` ON `session` (last_active_timestamp);
+
+CREATE INDEX `ix_race_participant_user_id` ON `race_participant` (user_id);
+
 source procedures/insert_result.sql;
+source procedures/merge_guest.sql;
@@ -0,0 +1,5 @@
+-- Widens the password hashes of an existing database for Argon2id without dropping any data, unlike
+-- replace.sql. It doesn't add the rest of schema.sql. The statement can be run again safely.
+
+-- Argon2id hashes are stored as PHC strings, which don't fit in the 32 bytes of the legacy hashes.
+ALTER TABLE `user` MODIFY password_hash VARBINARY(255);
@@ -8,6 +8,8 @@ import {
   AccountState,
   CurrentUserResponse,
   LogOutResponse,
+  SendVerificationCodeParams,
+  SendVerificationCodeResponse,
   SignInParams,
   SignInResponse,
   SignUpParams,
@@ -59,6 +61,16 @@ export function AccountServiceProvider({
     return true;
   }
 
+  async function sendVerificationCode(params: SendVerificationCodeParams) {
+    if (!canSignUpOrSignIn()) {
+      return {
+        status: "fail",
+      } as ServerResponse<SendVerificationCodeResponse>;
+    }
+
+    return post<SendVerificationCodeResponse>("/signup/code", params);
+  }
+
   async function signUp(params: SignUpParams) {
     if (!canSignUpOrSignIn()) {
       return { status: "fail" } as ServerResponse<SignInResponse>;
@@ -99,9 +111,13 @@ export function AccountServiceProvider({
       return { status: "fail" } as ServerResponse<LogOutResponse>;
     }
 
-    const response = await get<LogOutResponse>("/logout", {
-      credentials: "include",
-    });
+    const response = await post<LogOutResponse>(
+      "/logout",
+      {},
+      {
+        credentials: "include",
+      },
+    );
 
     if (response.status === "ok") {
       setAccountState({
@@ -112,7 +128,13 @@ export function AccountServiceProvider({
     return response;
   }
 
-  const accountService = { accountState, signUp, signIn, logOut };
+  const accountService = {
+    accountState,
+    sendVerificationCode,
+    signUp,
+    signIn,
+    logOut,
+  };
 
   return (
     <AccountService.Provider value={accountService}>
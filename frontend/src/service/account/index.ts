@@ -4,11 +4,17 @@ import { createService } from "..";
 
 export const AccountService = createService<{
   accountState: AccountState;
+  sendVerificationCode: (
+    params: SendVerificationCodeParams,
+  ) => Promise<ServerResponse<SendVerificationCodeResponse>>;
   signUp: (params: SignUpParams) => Promise<ServerResponse<SignUpResponse>>;
   signIn: (params: SignInParams) => Promise<ServerResponse<SignInResponse>>;
   logOut: () => Promise<ServerResponse<LogOutResponse>>;
 }>({
   accountState: { state: "notsignedin" },
+  sendVerificationCode: async () => {
+    return { status: "fail" };
+  },
   signUp: async () => {
     return { status: "fail" };
   },
@@ -36,6 +42,12 @@ export interface Account {
   email: string;
 }
 
+export interface SendVerificationCodeParams {
+  email: string;
+}
+
+export type SendVerificationCodeResponse = null;
+
 export interface SignUpParams {
   username: string;
   email: string;
@@ -1,5 +1,6 @@
 import { createService } from "..";
 import { TypingTestParams } from "../../service/preferences";
+import { Replay } from "../../typing-test/replay";
 import { ServerResponse } from "../server";
 
 export const ResultsService = createService<{
@@ -48,6 +49,7 @@ export interface Result {
   wpm: number;
   rawWpm: number;
   accuracy: number;
+  replay?: Replay;
 }
 
 export interface Stat {
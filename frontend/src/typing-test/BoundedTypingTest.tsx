@@ -17,6 +17,7 @@ import {
 } from "./stat";
 import Input, { InputHandle, InputOptions } from "./Input";
 import { TypingTestCallbacks } from "./props";
+import { Keystroke, toKeystroke } from "./replay";
 
 export interface BoundedTypingTestProps
   extends InputOptions,
@@ -41,6 +42,8 @@ const BoundedTypingTest = (
   const [start, setStart] = useState<number | undefined>(undefined);
   const [end, setEnd] = useState<number | undefined>(undefined);
 
+  const keystrokes = useRef<Keystroke[]>([]);
+
   const [charCounts, setCharCounts] = useState<CharCounts>({
     correctChars: 0,
     incorrectChars: 0,
@@ -64,16 +67,25 @@ const BoundedTypingTest = (
   useEffect(() => {
     if (end !== undefined && onTestFinish) {
       const duration = (end! - start!) / 1000;
-      onTestFinish({ attempt, duration, ...stats });
+      onTestFinish({
+        attempt,
+        duration,
+        keystrokes: keystrokes.current,
+        ...stats,
+      });
     }
   }, [end]);
 
   const handleAttemptUpdate = (newAttempt: string[]) => {
+    const now = performance.now();
     if (!start) {
-      setStart(performance.now());
+      setStart(now);
     }
     if (!end) {
       setAttempt(newAttempt);
+      keystrokes.current.push(
+        toKeystroke(now - (start ?? now), attempt, newAttempt),
+      );
 
       setCharCounts(
         calculateCharCounts({ test, attempt, newAttempt, charCounts }),
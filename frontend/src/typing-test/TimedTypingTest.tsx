@@ -10,6 +10,7 @@ import {
 } from "./stat";
 import Input, { InputHandle, InputOptions } from "./Input";
 import { TypingTestCallbacks } from "./props";
+import { Keystroke, toKeystroke } from "./replay";
 
 interface TimedTypingTestProps extends InputOptions, TypingTestCallbacks {
   generateTest: (length: number) => string[];
@@ -32,6 +33,8 @@ const TimedTypingTest = ({
   const [start, setStart] = useState<number | undefined>(undefined);
   const [end, setEnd] = useState<number | undefined>(undefined);
 
+  const keystrokes = useRef<Keystroke[]>([]);
+
   const [charCounts, setCharCounts] = useState<CharCounts>({
     correctChars: 0,
     incorrectChars: 0,
@@ -55,13 +58,19 @@ const TimedTypingTest = ({
   useEffect(() => {
     if (end !== undefined && onTestFinish) {
       const duration = (end! - start!) / 1000;
-      onTestFinish({ attempt, duration, ...stats });
+      onTestFinish({
+        attempt,
+        duration,
+        keystrokes: keystrokes.current,
+        ...stats,
+      });
     }
   }, [end]);
 
   const handleAttemptUpdate = (newAttempt: string[]) => {
+    const now = performance.now();
     if (!start) {
-      setStart(performance.now());
+      setStart(now);
       const intervalId = setInterval(() => {
         setProgress((progress) => {
           if (progress === 1) {
@@ -74,6 +83,9 @@ const TimedTypingTest = ({
     }
     if (!end) {
       setAttempt(newAttempt);
+      keystrokes.current.push(
+        toKeystroke(now - (start ?? now), attempt, newAttempt),
+      );
       if (onTestUpdate) onTestUpdate(attempt, newAttempt);
       if (newAttempt.length + PADDING > test.length) {
         setTest(generateTest(newAttempt.length + PADDING));
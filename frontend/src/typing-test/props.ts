@@ -1,5 +1,6 @@
 /* Common sets of props used in typing test components. */
 
+import { Keystroke } from "./replay";
 import { Stats } from "./stat";
 
 export interface TypingTestCallbacks {
@@ -11,4 +12,5 @@ export interface TypingTestCallbacks {
 export interface TestFinishEvent extends Stats {
   attempt: string[];
   duration: number;
+  keystrokes: Keystroke[];
 }
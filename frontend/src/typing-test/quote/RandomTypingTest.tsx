@@ -66,7 +66,7 @@ function Inner({
       notificationsService.addNotification(REPEATED_TEST_NOTIFICATION);
       return;
     }
-    const { wpm, rawWpm, accuracy } = event;
+    const { wpm, rawWpm, accuracy, keystrokes } = event;
     resultsService.reportResult({
       testParams: {
         mode: "quote",
@@ -78,6 +78,16 @@ function Inner({
       wpm,
       rawWpm,
       accuracy,
+      replay: {
+        testParams: {
+          mode: "quote",
+          params: {
+            length,
+            id: quoteId,
+          },
+        },
+        keystrokes,
+      },
     });
   };
 
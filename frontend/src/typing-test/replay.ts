@@ -0,0 +1,37 @@
+import { SeededTypingTestParams } from "../view/SpecificTypingTestView";
+
+/**
+ * A single change to the test input: milliseconds elapsed since the first keystroke, the number of
+ * characters removed from the end of the input, and the characters then appended to it.
+ */
+export type Keystroke = [number, number, string];
+
+/** The test that was attempted, along with every keystroke made during the attempt. */
+export interface Replay {
+  testParams: SeededTypingTestParams;
+  keystrokes: Keystroke[];
+}
+
+export function toKeystroke(
+  elapsed: number,
+  attempt: string[],
+  newAttempt: string[],
+): Keystroke {
+  // Characters are counted by code point, as the backend does.
+  const input = Array.from(attempt.join(" "));
+  const newInput = Array.from(newAttempt.join(" "));
+
+  let common = 0;
+  while (
+    common < input.length &&
+    common < newInput.length &&
+    input[common] === newInput[common]
+  ) {
+    common++;
+  }
+  return [
+    Math.round(elapsed),
+    input.length - common,
+    newInput.slice(common).join(""),
+  ];
+}
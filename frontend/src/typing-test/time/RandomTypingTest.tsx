@@ -61,7 +61,7 @@ const RandomTypingTest = ({
       notificationsService.addNotification(REPEATED_TEST_NOTIFICATION);
       return;
     }
-    const { wpm, rawWpm, accuracy } = event;
+    const { wpm, rawWpm, accuracy, keystrokes } = event;
     resultsService.reportResult({
       testParams: {
         mode: "time",
@@ -74,6 +74,17 @@ const RandomTypingTest = ({
       wpm,
       rawWpm,
       accuracy,
+      replay: {
+        testParams: {
+          mode: "time",
+          params: {
+            language,
+            duration,
+            seed,
+          },
+        },
+        keystrokes,
+      },
     });
   };
 
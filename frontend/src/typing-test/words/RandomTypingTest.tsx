@@ -64,7 +64,7 @@ const RandomTypingTest = ({
       notificationsService.addNotification(REPEATED_TEST_NOTIFICATION);
       return;
     }
-    const { wpm, rawWpm, accuracy } = event;
+    const { wpm, rawWpm, accuracy, keystrokes } = event;
     resultsService.reportResult({
       testParams: {
         mode: "words",
@@ -77,6 +77,17 @@ const RandomTypingTest = ({
       wpm,
       rawWpm,
       accuracy,
+      replay: {
+        testParams: {
+          mode: "words",
+          params: {
+            language,
+            length,
+            seed,
+          },
+        },
+        keystrokes,
+      },
     });
   };
 
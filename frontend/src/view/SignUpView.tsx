@@ -14,6 +14,8 @@ const SignUpView = () => {
   const {
     register,
     handleSubmit,
+    getValues,
+    trigger,
     formState: { errors },
   } = useForm();
 
@@ -23,6 +25,23 @@ const SignUpView = () => {
 
   const [loading, setLoading] = useState(false);
   const [serverError, setServerError] = useState("");
+  const [codeSentTo, setCodeSentTo] = useState("");
+
+  const sendVerificationCode = async () => {
+    if (!(await trigger("email"))) {
+      return;
+    }
+    const email = getValues("email");
+    setLoading(true);
+    const response = await accountService.sendVerificationCode({ email });
+    if (response.status === "ok") {
+      setCodeSentTo(email);
+      setServerError("");
+    } else if (response.status === "err") {
+      setServerError(response.reason);
+    }
+    setLoading(false);
+  };
 
   return (
     <form
@@ -100,6 +119,13 @@ const SignUpView = () => {
         helperText={errors.email?.message?.toString()}
       />
 
+      <Button variant="text" onClick={sendVerificationCode} disabled={loading}>
+        Send verification code
+      </Button>
+      {codeSentTo !== "" && (
+        <p>A verification code was sent to {codeSentTo}</p>
+      )}
+
       <TextField
         className="SignUpInput"
         variant="filled"
@@ -25,12 +25,12 @@ function getTypingTestView({ mode, params }: SeededTypingTestParams) {
   }
 }
 
-type SeededTypingTestParams =
+export type SeededTypingTestParams =
   | WordsSeededTypingTestParams
   | TimeSeededTypingTestParams
   | QuoteSeededTypingTestParams;
 
-interface WordsSeededTypingTestParams {
+export interface WordsSeededTypingTestParams {
   mode: "words";
   params: {
     language: Language;
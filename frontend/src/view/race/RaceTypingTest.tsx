@@ -0,0 +1,122 @@
+import { useEffect } from "react";
+import { useService } from "../../service";
+import { Language, StaticContentService } from "../../service/staticcontent";
+import { useLanguage, useQuotes } from "../../service/staticcontent/hooks";
+import BoundedTypingTest from "../../typing-test/BoundedTypingTest";
+import TimedTypingTest from "../../typing-test/TimedTypingTest";
+import { randomWords } from "../../typing-test/gen";
+import { TypingTestCallbacks } from "../../typing-test/props";
+import { Seed } from "../../util/prng";
+import { SeededTypingTestParams } from "../SpecificTypingTestView";
+
+// Words can't be skipped in races, since progress is counted in words.
+
+const RaceTypingTest = ({
+  testParams,
+  ...callbacks
+}: { testParams: SeededTypingTestParams } & TypingTestCallbacks) => {
+  const { mode, params } = testParams;
+  switch (mode) {
+    case "words":
+      return <WordsRaceTypingTest {...params} {...callbacks} />;
+    case "time":
+      return <TimeRaceTypingTest {...params} {...callbacks} />;
+    case "quote":
+      return <QuoteRaceTypingTest id={params.id} {...callbacks} />;
+  }
+};
+
+const WordsRaceTypingTest = ({
+  language,
+  length,
+  seed,
+  ...callbacks
+}: {
+  language: Language;
+  length: number;
+  seed: Seed;
+} & TypingTestCallbacks) => {
+  const words = useLanguage(language);
+  if (words === undefined) {
+    return;
+  }
+
+  return (
+    <BoundedTypingTest
+      test={randomWords(seed, words, length)}
+      allowSkippingWords={false}
+      {...callbacks}
+    />
+  );
+};
+
+const TimeRaceTypingTest = ({
+  language,
+  duration,
+  seed,
+  ...callbacks
+}: {
+  language: Language;
+  duration: number;
+  seed: Seed;
+} & TypingTestCallbacks) => {
+  const words = useLanguage(language);
+  if (words === undefined) {
+    return;
+  }
+
+  return (
+    <TimedTypingTest
+      generateTest={(count) => randomWords(seed, words, count)}
+      duration={duration}
+      allowSkippingWords={false}
+      {...callbacks}
+    />
+  );
+};
+
+const QuoteRaceTypingTest = ({
+  id,
+  ...callbacks
+}: { id: number } & TypingTestCallbacks) => {
+  const quotes = useQuotes();
+  if (quotes === undefined) {
+    return;
+  }
+
+  return (
+    <BoundedTypingTest
+      test={quotes.quotes[id].text.split(" ")}
+      allowSkippingWords={false}
+      {...callbacks}
+    />
+  );
+};
+
+/**
+ * The number of words in the test, if it is known yet. Time mode tests have
+ * no end to their words.
+ */
+export function useTestLength(
+  testParams: SeededTypingTestParams | undefined,
+): number | undefined {
+  const { quotes, requestQuotes } = useService(StaticContentService);
+  const isQuote = testParams?.mode === "quote";
+
+  useEffect(() => {
+    if (isQuote) {
+      requestQuotes();
+    }
+  }, [isQuote, requestQuotes]);
+
+  switch (testParams?.mode) {
+    case "words":
+      return testParams.params.length;
+    case "quote":
+      return quotes?.quotes[testParams.params.id].text.split(" ").length;
+    default:
+      return undefined;
+  }
+}
+
+export default RaceTypingTest;
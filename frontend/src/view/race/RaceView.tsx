@@ -1,23 +1,26 @@
 import { useEffect, useRef, useState } from "react";
-import BoundedTypingTest from "../../typing-test/BoundedTypingTest";
-import { randomWords } from "../../typing-test/gen";
 import { LinearProgress } from "@mui/material";
-import { Seed } from "../../util/prng";
-import english from "../../static/words/english.json";
 import { Account, AccountService } from "../../service/account";
 import { useService } from "../../service";
+import { NotificationsService } from "../../service/notifications";
+import { SeededTypingTestParams } from "../SpecificTypingTestView";
+import RaceTypingTest, { useTestLength } from "./RaceTypingTest";
 
-const TEST_LENGTH = 20;
+const PROTOCOL_VERSION = 1;
 
 type State = "waiting" | "prepare" | "start" | "finish" | "timeout";
 
 const RaceView = () => {
+  const { addNotification } = useService(NotificationsService);
   const { accountState } = useService(AccountService);
   const socket = useRef<WebSocket | null>(null);
 
   const [opponents, setOpponents] = useState<Opponent[]>([]);
   const [results, setResults] = useState<Result[]>([]);
-  const [seed, setSeed] = useState<Seed | undefined>(undefined);
+  const [testParams, setTestParams] = useState<
+    SeededTypingTestParams | undefined
+  >(undefined);
+  const testLength = useTestLength(testParams);
 
   const [state, setState] = useState<State>("waiting");
 
@@ -42,14 +45,53 @@ const RaceView = () => {
         break;
       case "start":
         {
-          const { seed } = payload;
+          const { testParams } = payload;
           setState("prepare");
-          setSeed(seed);
+          setTestParams(testParams);
           setTimeout(() => {
             setState("start");
           }, 5 * 1000);
         }
         break;
+      case "resume":
+        {
+          const { testParams, timeUntilRaceStart, players } = payload;
+          const ownUsername =
+            accountState.state === "signedin"
+              ? accountState.account.username
+              : undefined;
+          setTestParams(testParams);
+          // Only players who are still racing are sent this message.
+          const others = players.filter(
+            ({ username }) => username !== ownUsername,
+          );
+          setOpponents(
+            others
+              .filter(({ result }) => result === null)
+              .map(({ username, progress }) => ({ username, progress })),
+          );
+          setResults(
+            others.flatMap(({ username, result }) =>
+              result === null ? [] : [{ username, result }],
+            ),
+          );
+          setUserProgress(
+            players.find(({ username }) => username === ownUsername)
+              ?.progress ?? 0,
+          );
+          const msUntilStart =
+            timeUntilRaceStart.secs * 1000 +
+            timeUntilRaceStart.nanos / 1_000_000;
+          if (msUntilStart > 0) {
+            setState("prepare");
+            setTimeout(() => {
+              setState("start");
+            }, msUntilStart);
+          } else {
+            setState("start");
+          }
+        }
+        break;
       case "update":
         {
           const { username, progress } = payload;
@@ -97,6 +139,12 @@ const RaceView = () => {
           });
         }
         break;
+      case "error":
+        {
+          const { title, body } = payload;
+          addNotification({ type: "Error", title, body });
+        }
+        break;
     }
   };
 
@@ -104,7 +152,9 @@ const RaceView = () => {
     if (accountState.state !== "signedin") {
       return;
     }
-    socket.current = new WebSocket("ws://localhost:8080/race");
+    socket.current = new WebSocket(
+      `ws://localhost:8080/race?protocolVersion=${PROTOCOL_VERSION}`,
+    );
     socket.current.addEventListener("error", () => {
       console.error("error has occurred!");
     });
@@ -142,20 +192,19 @@ const RaceView = () => {
         account={accountState.account}
         userProgress={userProgress}
         userResult={userResult}
+        testLength={testLength}
         opponents={opponents}
         results={results}
       />
       {state === "start" && (
-        <BoundedTypingTest
-          test={randomWords(seed!, english, TEST_LENGTH)}
-          allowSkippingWords={false}
+        <RaceTypingTest
+          testParams={testParams!}
           onTestUpdate={(_, attempt) => {
             const userProgress = attempt.length - 1;
             setUserProgress(userProgress);
             const msg = JSON.stringify({
               kind: "update",
               payload: {
-                username: accountState.account.username,
                 progress: userProgress,
               },
             });
@@ -166,10 +215,7 @@ const RaceView = () => {
             setState("finish");
             const msg = JSON.stringify({
               kind: "finish",
-              payload: {
-                username: accountState.account.username,
-                result: duration,
-              },
+              payload: {},
             });
             socket.current?.send(msg);
           }}
@@ -183,12 +229,14 @@ const RaceProgress = ({
   account,
   userProgress,
   userResult,
+  testLength,
   opponents,
   results,
 }: {
   account: Account;
   userProgress: number;
   userResult?: number;
+  testLength?: number;
   opponents: Opponent[];
   results: Result[];
 }) => {
@@ -219,41 +267,54 @@ const RaceProgress = ({
         return disconnected === undefined ? (
           <div key={username} className="Opponent">
             {username}{" "}
-            <LinearProgress
-              variant="determinate"
-              value={(progress / TEST_LENGTH) * 100}
-            />
+            <Progress progress={progress} testLength={testLength} />
           </div>
         ) : (
           <div key={username} className="Disconnected" style={{ opacity: 0.5 }}>
             {username} {`(${disconnectReasonToString(disconnected)})`}
-            <LinearProgress
-              variant="determinate"
-              value={(progress / TEST_LENGTH) * 100}
-            />
+            <Progress progress={progress} testLength={testLength} />
           </div>
         );
       })}
       {userResult === undefined && (
         <div className="Account">
           {account.username} {"(You)"}
-          <LinearProgress
-            variant="determinate"
-            value={(userProgress / TEST_LENGTH) * 100}
-          />
+          <Progress progress={userProgress} testLength={testLength} />
         </div>
       )}
     </div>
   );
 };
 
+// Time mode tests have no length to measure progress against, so words are
+// counted instead.
+const Progress = ({
+  progress,
+  testLength,
+}: {
+  progress: number;
+  testLength?: number;
+}) => {
+  if (testLength === undefined) {
+    return <div>{progress} words</div>;
+  }
+  return (
+    <LinearProgress
+      variant="determinate"
+      value={(progress / testLength) * 100}
+    />
+  );
+};
+
 type Msg =
   | JoinedMsg
   | StartMsg
+  | ResumeMsg
   | UpdateMsg
   | FinishMsg
   | DisconnectMsg
-  | TimeoutMsg;
+  | TimeoutMsg
+  | ErrorMsg;
 
 interface JoinedMsg {
   kind: "joined";
@@ -265,7 +326,17 @@ interface JoinedMsg {
 interface StartMsg {
   kind: "start";
   payload: {
-    seed: Seed;
+    testParams: SeededTypingTestParams;
+  };
+}
+
+interface ResumeMsg {
+  kind: "resume";
+  payload: {
+    testParams: SeededTypingTestParams;
+    timeUntilRaceStart: { secs: number; nanos: number };
+    elapsed: { secs: number; nanos: number };
+    players: { username: string; progress: number; result: number | null }[];
   };
 }
 
@@ -311,6 +382,14 @@ interface TimeoutMsg {
   };
 }
 
+interface ErrorMsg {
+  kind: "error";
+  payload: {
+    title: string;
+    body: string;
+  };
+}
+
 interface Opponent {
   username: string;
   progress: number;
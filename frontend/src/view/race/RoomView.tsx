@@ -3,14 +3,10 @@ import { useParams } from "react-router-dom";
 import { useService } from "../../service";
 import { AccountService } from "../../service/account";
 import { Button } from "@mui/material";
-import BoundedTypingTest from "../../typing-test/BoundedTypingTest";
 import { TestFinishEvent } from "../../typing-test/props";
 import { NotificationsService } from "../../service/notifications";
-import { Seed } from "../../util/prng";
-import { randomWords } from "../../typing-test/gen";
-import english from "../../static/words/english.json";
-
-const TEST_LENGTH = 10;
+import { SeededTypingTestParams } from "../SpecificTypingTestView";
+import RaceTypingTest from "./RaceTypingTest";
 
 function RoomView() {
   const { room } = useParams();
@@ -102,7 +98,8 @@ function RoomView() {
       case "prepare":
         setState({
           kind: "preparing",
-          ...payload,
+          timeUntilRaceStart: payload.timeUntilRaceStart,
+          testParams: payload.testParams,
         });
         const interval = setInterval(() => {
           setState((state) => {
@@ -111,7 +108,7 @@ function RoomView() {
             }
             const timeLeft = state.timeUntilRaceStart.secs - 1;
             if (timeLeft == 0) {
-              setState({ kind: "racing", seed: state.seed });
+              setState({ kind: "racing", testParams: state.testParams });
               setOtherPlayers((otherPlayers) =>
                 otherPlayers.map((otherPlayer) => {
                   return {
@@ -150,6 +147,21 @@ function RoomView() {
           });
         });
         break;
+      case "roundOver":
+        setState({ kind: "notReady" });
+        setOtherPlayers((otherPlayers) =>
+          otherPlayers.map((otherPlayer) => {
+            return { ...otherPlayer, state: { kind: "notReady" } };
+          }),
+        );
+        addNotification({
+          type: "Info",
+          title: `Round ${payload.nRoundsPlayed} is over`,
+          body: payload.scoreboard
+            .map(({ username, points }) => `${username}: ${points}`)
+            .join(", "),
+        });
+        break;
       case "error":
         const { title, body } = payload;
         addNotification({ type: "Error", title, body });
@@ -272,11 +284,10 @@ function RoomView() {
           </div>
         ))}
       {state.kind === "racing" && (
-        <BoundedTypingTest
-          test={randomWords(state.seed, english, TEST_LENGTH)}
+        <RaceTypingTest
+          testParams={state.testParams}
           onTestUpdate={handleTestUpdate}
           onTestFinish={handleTestFinish}
-          allowSkippingWords={false}
         />
       )}
       {state.kind === "finished" && (
@@ -301,8 +312,12 @@ function RoomView() {
 type State =
   | { kind: "notReady" }
   | { kind: "ready" }
-  | { kind: "preparing"; timeUntilRaceStart: { secs: number }; seed: Seed }
-  | { kind: "racing"; seed: Seed }
+  | {
+      kind: "preparing";
+      timeUntilRaceStart: { secs: number };
+      testParams: SeededTypingTestParams;
+    }
+  | { kind: "racing"; testParams: SeededTypingTestParams }
   | { kind: "finished"; duration: { secs: number; nanos: number } };
 
 type OtherPlayerState =
@@ -325,6 +340,7 @@ type Msg =
   | PrepareMsg
   | UpdateMsg
   | FinishMsg
+  | RoundOverMsg
   | ErrorMsg;
 
 interface InitMsg {
@@ -372,7 +388,7 @@ interface PrepareMsg {
   kind: "prepare";
   payload: {
     timeUntilRaceStart: { secs: number };
-    seed: Seed;
+    testParams: SeededTypingTestParams;
   };
 }
 
@@ -392,6 +408,14 @@ interface FinishMsg {
   };
 }
 
+interface RoundOverMsg {
+  kind: "roundOver";
+  payload: {
+    nRoundsPlayed: number;
+    scoreboard: { username: string; points: number }[];
+  };
+}
+
 interface ErrorMsg {
   kind: "error";
   payload: {
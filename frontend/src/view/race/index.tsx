@@ -22,7 +22,7 @@ function Menu() {
   );
 
   const createRoom = () => {
-    post<{ roomId: number }>(
+    post<{ roomId: string }>(
       "/room/create",
       {},
       { credentials: "include" },